@@ -0,0 +1,146 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::utils::{get_steam_patch_dir, write_file_atomic};
+
+// Pristine copies of the Steam files we patch, stored as ~/steam-patch/pristine/<sha256>
+// with an index of which hash we wrote to which file.
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct BackupIndex {
+    files: HashMap<PathBuf, BackupEntry>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct BackupEntry {
+    pristine_hash: String,
+    patched_hash: Option<String>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Restore {
+    Restored,
+    /// The file on disk isn't the one we wrote, most likely Steam updated it
+    Changed,
+    NoBackup,
+}
+
+pub fn hash(content: &[u8]) -> String {
+    Sha256::digest(content)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+fn get_backup_dir() -> PathBuf {
+    get_steam_patch_dir().join("pristine")
+}
+
+fn read_index() -> BackupIndex {
+    fs::read_to_string(get_backup_dir().join("index.json"))
+        .ok()
+        .and_then(|content| serde_json::from_str(&content).ok())
+        .unwrap_or_default()
+}
+
+fn write_index(index: &BackupIndex) -> Result<(), String> {
+    let content = serde_json::to_string_pretty(index)
+        .map_err(|e| format!("Failed to serialize backup index: {}", e))?;
+    fs::write(get_backup_dir().join("index.json"), content)
+        .map_err(|e| format!("Failed to write backup index: {}", e))?;
+    prune(index);
+    Ok(())
+}
+
+// Removes pristine copies no file refers to anymore, ie. after a Steam update
+fn prune(index: &BackupIndex) {
+    let entries = match fs::read_dir(get_backup_dir()) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name == "index.json" || index.files.values().any(|file| file.pristine_hash == name) {
+            continue;
+        }
+        let _ = fs::remove_file(entry.path());
+    }
+}
+
+/// Saves the current content of `path` as its pristine copy, unless the file is still
+/// the patched version we wrote last time and the pristine copy is already stored.
+pub fn backup_before_write(path: &Path) -> Result<(), String> {
+    let content = fs::read(path).map_err(|e| format!("Failed to read {:?} for backup: {}", path, e))?;
+    let current_hash = hash(&content);
+
+    let mut index = read_index();
+    if let Some(entry) = index.files.get(path) {
+        if entry.patched_hash.as_deref() == Some(current_hash.as_str()) {
+            return Ok(());
+        }
+    }
+
+    let backup_dir = get_backup_dir();
+    fs::create_dir_all(&backup_dir)
+        .map_err(|e| format!("Failed to create backup directory {:?}: {}", backup_dir, e))?;
+
+    let backup_path = backup_dir.join(&current_hash);
+    if !backup_path.exists() {
+        fs::write(&backup_path, &content)
+            .map_err(|e| format!("Failed to write pristine copy of {:?}: {}", path, e))?;
+        println!("Saved pristine copy of {:?}", path);
+    }
+
+    index.files.insert(
+        path.to_path_buf(),
+        BackupEntry {
+            pristine_hash: current_hash,
+            patched_hash: None,
+        },
+    );
+    write_index(&index)
+}
+
+/// Records the hash of the content we wrote to `path`.
+pub fn record_written(path: &Path, content: &[u8]) -> Result<(), String> {
+    let mut index = read_index();
+    match index.files.get_mut(path) {
+        Some(entry) => entry.patched_hash = Some(hash(content)),
+        None => return Err(format!("No pristine copy of {:?} was saved", path)),
+    }
+    write_index(&index)
+}
+
+/// Puts the pristine copy back, only when the file on disk is still the one we wrote.
+pub fn restore(path: &Path) -> Result<Restore, String> {
+    let mut index = read_index();
+    let entry = match index.files.get(path) {
+        Some(entry) => entry.clone(),
+        None => return Ok(Restore::NoBackup),
+    };
+
+    let current = fs::read(path).map_err(|e| format!("Failed to read {:?}: {}", path, e))?;
+    if entry.patched_hash.as_deref() != Some(hash(&current).as_str()) {
+        println!("{:?} changed since it was patched, leaving it as is.", path);
+        index.files.remove(path);
+        write_index(&index)?;
+        return Ok(Restore::Changed);
+    }
+
+    let pristine_path = get_backup_dir().join(&entry.pristine_hash);
+    let pristine = fs::read(&pristine_path)
+        .map_err(|e| format!("Failed to read pristine copy {:?}: {}", pristine_path, e))?;
+    if hash(&pristine) != entry.pristine_hash {
+        return Err(format!("Pristine copy {:?} is corrupted", pristine_path));
+    }
+
+    write_file_atomic(path, &pristine).map_err(|e| format!("Failed to restore {:?}: {}", path, e))?;
+    println!("Restored pristine copy of {:?}", path);
+
+    index.files.remove(path);
+    write_index(&index)?;
+    Ok(Restore::Restored)
+}
@@ -0,0 +1,416 @@
+use futures::future::BoxFuture;
+use futures::{SinkExt, StreamExt};
+use serde::de::DeserializeOwned;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::net::TcpStream;
+use tokio::sync::{broadcast, mpsc, oneshot};
+use tokio::time::{sleep, timeout, Duration};
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{connect_async, MaybeTlsStream, WebSocketStream};
+
+// Chrome DevTools Protocol client for Steam's CEF debugger. A background task owns the
+// websocket, sends queued requests, reads replies and hands each one to the caller waiting
+// for its id. The connection is reopened in the background whenever it drops.
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+// Events kept for subscribers that fall behind
+const EVENT_BUFFER: usize = 256;
+
+type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// Finds the websocket URL of the tab to debug, `None` when it isn't up
+pub type TargetSource = Arc<dyn Fn() -> BoxFuture<'static, Option<String>> + Send + Sync>;
+
+/// A page listed by the `/json` endpoint of the debugger
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct TabInfo {
+    #[serde(default)]
+    pub id: String,
+    #[serde(default)]
+    pub title: String,
+    #[serde(default)]
+    pub url: String,
+    #[serde(default, rename = "type")]
+    pub kind: String,
+    /// Empty while another debugger is attached to the tab
+    #[serde(default)]
+    pub web_socket_debugger_url: String,
+}
+
+/// Which tab to debug, `"QuickAccess.*"` is matched against the whole title and
+/// `{ url = "..." }` is searched for in the URL. Steam adds suffixes like `_uid2` to the
+/// titles of some of its windows. The pattern is compiled when the selector is parsed, one
+/// that doesn't compile fails the patch set.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(try_from = "TabPattern", into = "TabPattern")]
+pub struct TabSelector {
+    pattern: TabPattern,
+    regex: Regex,
+}
+
+/// A tab selector the way patch sets write it
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[serde(untagged)]
+pub enum TabPattern {
+    Title(String),
+    Url { url: String },
+}
+
+impl TryFrom<TabPattern> for TabSelector {
+    type Error = String;
+
+    fn try_from(pattern: TabPattern) -> Result<Self, Self::Error> {
+        let source = match &pattern {
+            TabPattern::Title(title) => format!("^(?:{})$", title),
+            TabPattern::Url { url } => url.clone(),
+        };
+        match Regex::new(&source) {
+            Ok(regex) => Ok(TabSelector { pattern, regex }),
+            Err(e) => Err(format!("Invalid tab pattern {:?}: {}", pattern, e)),
+        }
+    }
+}
+
+impl From<TabSelector> for TabPattern {
+    fn from(selector: TabSelector) -> Self {
+        selector.pattern
+    }
+}
+
+impl PartialEq for TabSelector {
+    fn eq(&self, other: &Self) -> bool {
+        self.pattern == other.pattern
+    }
+}
+
+impl Eq for TabSelector {}
+
+impl Hash for TabSelector {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.pattern.hash(state)
+    }
+}
+
+impl Default for TabSelector {
+    fn default() -> Self {
+        TabSelector::title("SharedJSContext")
+    }
+}
+
+impl TabSelector {
+    /// Tab whose whole title matches `pattern`, panics on an invalid regex so it is only
+    /// meant for the fixed patterns of the code
+    pub fn title(pattern: &str) -> TabSelector {
+        TabSelector::try_from(TabPattern::Title(pattern.to_string())).expect("Tab pattern should be valid")
+    }
+
+    pub fn is_default(&self) -> bool {
+        *self == TabSelector::default()
+    }
+
+    pub fn matches(&self, tab: &TabInfo) -> bool {
+        let text = match self.pattern {
+            TabPattern::Title(_) => &tab.title,
+            TabPattern::Url { .. } => &tab.url,
+        };
+        self.regex.is_match(text)
+    }
+}
+
+impl fmt::Display for TabSelector {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.pattern {
+            TabPattern::Title(title) => write!(f, "'{}'", title),
+            TabPattern::Url { url } => write!(f, "with URL '{}'", url),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum CdpError {
+    /// The connection dropped before the reply came
+    Disconnected,
+    Timeout,
+    /// The debugger rejected the request
+    Protocol { code: i64, message: String },
+    /// The evaluated script threw
+    Exception(ExceptionDetails),
+    /// The result of a query doesn't have the expected type
+    Decode(String),
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ExceptionDetails {
+    pub text: String,
+    #[serde(default)]
+    pub line_number: i64,
+    #[serde(default)]
+    pub column_number: i64,
+    /// The thrown value, its description holds the message and stack of errors
+    #[serde(default)]
+    pub exception: Option<Value>,
+    /// Script the exception was thrown in, the `sourceURL` of evaluated scripts
+    #[serde(default)]
+    pub url: String,
+    #[serde(default)]
+    pub stack_trace: Option<StackTrace>,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct StackTrace {
+    #[serde(default)]
+    pub call_frames: Vec<CallFrame>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct CallFrame {
+    #[serde(default)]
+    pub url: String,
+}
+
+/// A message from the debugger that isn't a reply, like `Runtime.consoleAPICalled`
+#[derive(Debug, Clone)]
+pub struct Event {
+    pub method: String,
+    pub params: Value,
+}
+
+impl fmt::Display for CdpError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CdpError::Disconnected => write!(f, "Not connected to Steam"),
+            CdpError::Timeout => write!(f, "Steam didn't answer in time"),
+            CdpError::Protocol { code, message } => write!(f, "Steam rejected the request ({}): {}", code, message),
+            CdpError::Exception(details) => write!(f, "{}", details),
+            CdpError::Decode(message) => write!(f, "Unexpected result from Steam: {}", message),
+        }
+    }
+}
+
+impl fmt::Display for ExceptionDetails {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let description = self
+            .exception
+            .as_ref()
+            .and_then(|exception| exception["description"].as_str())
+            .unwrap_or(&self.text);
+        write!(f, "{} at {}:{}", description, self.line_number, self.column_number)
+    }
+}
+
+struct Request {
+    id: u64,
+    method: String,
+    params: Value,
+    reply: oneshot::Sender<Result<Value, CdpError>>,
+}
+
+pub struct CdpClient {
+    requests: mpsc::UnboundedSender<Request>,
+    next_id: AtomicU64,
+    events: broadcast::Sender<Event>,
+}
+
+impl CdpClient {
+    /// Starts connecting to the tab `target` finds, requests wait until the connection is up.
+    /// The `setup` methods, like `Runtime.enable`, are sent every time it connects.
+    pub fn new(target: TargetSource, setup: &[&str]) -> CdpClient {
+        let (requests, receiver) = mpsc::unbounded_channel();
+        let (events, _) = broadcast::channel(EVENT_BUFFER);
+        let setup = setup.iter().map(|method| method.to_string()).collect();
+        tokio::spawn(run(target, setup, receiver, events.clone()));
+        CdpClient {
+            requests,
+            next_id: AtomicU64::new(1),
+            events,
+        }
+    }
+
+    /// Events sent by the tab from now on, the domain has to be enabled in `setup`.
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.events.subscribe()
+    }
+
+    /// Sends a command and waits for its result.
+    pub async fn call(&self, method: &str, params: Value) -> Result<Value, CdpError> {
+        let (reply, response) = oneshot::channel();
+        let request = Request {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            method: method.to_string(),
+            params,
+            reply,
+        };
+        self.requests.send(request).map_err(|_| CdpError::Disconnected)?;
+
+        match timeout(REQUEST_TIMEOUT, response).await {
+            Ok(Ok(result)) => result,
+            Ok(Err(_)) => Err(CdpError::Disconnected),
+            Err(_) => Err(CdpError::Timeout),
+        }
+    }
+
+    /// Runs `Runtime.evaluate`, a thrown exception is returned as `CdpError::Exception`.
+    pub async fn evaluate(&self, params: Value) -> Result<Value, CdpError> {
+        let mut result = self.call("Runtime.evaluate", params).await?;
+        if let Some(details) = result.get("exceptionDetails") {
+            let details = serde_json::from_value(details.clone()).unwrap_or_else(|_| ExceptionDetails {
+                text: details.to_string(),
+                line_number: 0,
+                column_number: 0,
+                exception: None,
+                url: String::new(),
+                stack_trace: None,
+            });
+            return Err(CdpError::Exception(details));
+        }
+        Ok(result["result"].take())
+    }
+
+    /// Evaluates `expression`, waits for it if it is a promise and deserializes its value.
+    /// `undefined` comes back as `null`, so `Option<T>` fits values that may be missing.
+    pub async fn query<T: DeserializeOwned>(&self, expression: &str) -> Result<T, CdpError> {
+        let params = serde_json::json!({
+            "expression": expression,
+            "returnByValue": true,
+            "awaitPromise": true,
+        });
+        let mut result = self.evaluate(params).await?;
+        let value = result.get_mut("value").map(Value::take).unwrap_or(Value::Null);
+        serde_json::from_value(value).map_err(|e| CdpError::Decode(e.to_string()))
+    }
+}
+
+// Keeps a session open for as long as the client exists
+async fn run(
+    target: TargetSource,
+    setup: Vec<String>,
+    mut requests: mpsc::UnboundedReceiver<Request>,
+    events: broadcast::Sender<Event>,
+) {
+    // Requests made while disconnected, sent once the connection is back
+    let mut queued: Vec<Request> = Vec::new();
+    loop {
+        if let Some(url) = target().await {
+            match session(&url, &setup, &mut queued, &mut requests, &events).await {
+                Ok(()) => return,
+                Err(e) => eprintln!("Steam debugger connection lost: {}", e),
+            }
+        }
+
+        let retry = sleep(RECONNECT_DELAY);
+        tokio::pin!(retry);
+        loop {
+            tokio::select! {
+                _ = &mut retry => break,
+                request = requests.recv() => match request {
+                    Some(request) => {
+                        // Callers that timed out meanwhile don't need their request anymore
+                        queued.retain(|request| !request.reply.is_closed());
+                        queued.push(request);
+                    }
+                    None => return,
+                },
+            }
+        }
+    }
+}
+
+// Returns Ok once the client is gone, Err when the connection failed
+async fn session(
+    url: &str,
+    setup: &[String],
+    queued: &mut Vec<Request>,
+    requests: &mut mpsc::UnboundedReceiver<Request>,
+    events: &broadcast::Sender<Event>,
+) -> Result<(), String> {
+    let (mut socket, _) = timeout(CONNECT_TIMEOUT, connect_async(url))
+        .await
+        .map_err(|_| "Timed out connecting".to_string())?
+        .map_err(|e| e.to_string())?;
+    println!("Connected to Steam debugger: {}", url);
+    // Id 0 is never handed out, nobody waits for these replies
+    for method in setup {
+        let message = serde_json::json!({ "id": 0, "method": method });
+        socket.send(Message::Text(message.to_string())).await.map_err(|e| e.to_string())?;
+    }
+
+    let mut pending: HashMap<u64, oneshot::Sender<Result<Value, CdpError>>> = HashMap::new();
+    for request in queued.drain(..) {
+        send_request(&mut socket, &mut pending, request).await?;
+    }
+    loop {
+        tokio::select! {
+            request = requests.recv() => match request {
+                Some(request) => send_request(&mut socket, &mut pending, request).await?,
+                None => return Ok(()),
+            },
+            message = socket.next() => match message {
+                Some(Ok(Message::Text(text))) => handle_message(&text, &mut pending, events),
+                Some(Ok(Message::Close(_))) | None => return Err("Closed by Steam".to_string()),
+                Some(Ok(_)) => continue,
+                Some(Err(e)) => return Err(e.to_string()),
+            },
+        }
+    }
+}
+
+async fn send_request(
+    socket: &mut Socket,
+    pending: &mut HashMap<u64, oneshot::Sender<Result<Value, CdpError>>>,
+    request: Request,
+) -> Result<(), String> {
+    // The caller already gave up waiting
+    if request.reply.is_closed() {
+        return Ok(());
+    }
+    let message = serde_json::json!({
+        "id": request.id,
+        "method": request.method,
+        "params": request.params,
+    });
+    socket.send(Message::Text(message.to_string())).await.map_err(|e| e.to_string())?;
+    pending.insert(request.id, request.reply);
+    Ok(())
+}
+
+// Hands a reply to the request waiting for it, or broadcasts an event
+fn handle_message(text: &str, pending: &mut HashMap<u64, oneshot::Sender<Result<Value, CdpError>>>, events: &broadcast::Sender<Event>) {
+    let mut message: Value = match serde_json::from_str(text) {
+        Ok(message) => message,
+        Err(_) => return,
+    };
+    // Events have no id
+    if let Some(method) = message["method"].as_str().filter(|_| message.get("id").is_none()) {
+        // Nobody listening is fine
+        let _ = events.send(Event {
+            method: method.to_string(),
+            params: message["params"].take(),
+        });
+        return;
+    }
+    let reply = match message["id"].as_u64().and_then(|id| pending.remove(&id)) {
+        Some(reply) => reply,
+        None => return,
+    };
+    let result = match message.get("error") {
+        Some(error) => Err(CdpError::Protocol {
+            code: error["code"].as_i64().unwrap_or_default(),
+            message: error["message"].as_str().unwrap_or_default().to_string(),
+        }),
+        None => Ok(message["result"].take()),
+    };
+    let _ = reply.send(result);
+}
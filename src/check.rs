@@ -0,0 +1,230 @@
+use serde::Serialize;
+use std::path::PathBuf;
+
+use crate::config;
+use crate::devices::{create_device, get_device_name};
+use crate::engine::{self, Outcome};
+use crate::patch::Patch;
+use crate::patch_set;
+use crate::planner::{self, Conflict};
+use crate::steam_version::{self, SteamVersion};
+
+// `steam-patch check`: runs the patch matching against the installed Steam client
+// without writing anything and reports which patches would apply.
+
+#[derive(Serialize, Debug)]
+pub struct CheckReport {
+    pub device: Option<String>,
+    pub steam: SteamVersion,
+    /// A patch set with a build range covers the installed client
+    pub supported: bool,
+    /// Files holding the needle of at least one patch
+    pub files: Vec<PathBuf>,
+    pub patches: Vec<PatchCheck>,
+    pub conflicts: Vec<Conflict>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct PatchCheck {
+    pub id: String,
+    pub description: String,
+    pub file: Option<PathBuf>,
+    pub status: CheckStatus,
+    pub matches: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckStatus {
+    Found,
+    NotFound,
+    /// The needle matches more than once
+    Ambiguous,
+    /// Left out by the planner as a copy of another patch
+    Duplicate,
+    /// Injected over the CEF debugger, nothing to look for in the files
+    Injected,
+    /// Left out by the planner because it conflicts with another patch
+    Conflict,
+    Error,
+}
+
+impl CheckStatus {
+    fn label(&self) -> &'static str {
+        match self {
+            CheckStatus::Found => "found",
+            CheckStatus::NotFound => "not found",
+            CheckStatus::Ambiguous => "ambiguous",
+            CheckStatus::Duplicate => "duplicate",
+            CheckStatus::Injected => "injected",
+            CheckStatus::Conflict => "conflict",
+            CheckStatus::Error => "error",
+        }
+    }
+}
+
+pub fn check() -> Result<CheckReport, String> {
+    let device = create_device().ok_or("Failed to detect the device")?;
+    let (injected, patches): (Vec<Patch>, Vec<Patch>) = device.get_patches().into_iter().partition(Patch::is_injected);
+    let plan = planner::plan(patches);
+
+    let steam = steam_version::detect();
+    let mut report = CheckReport {
+        device: get_device_name(),
+        supported: patch_set::is_supported(&steam),
+        steam,
+        files: Vec::new(),
+        patches: Vec::new(),
+        conflicts: plan.conflicts.clone(),
+    };
+    for patch in &injected {
+        report.patches.push(PatchCheck {
+            id: patch.id.clone(),
+            description: patch.description.clone(),
+            file: None,
+            status: CheckStatus::Injected,
+            matches: 0,
+            error: None,
+        });
+    }
+    for (name, patch) in &plan.rejected {
+        let conflict = plan.rejection_of(name);
+        let status = match conflict {
+            Some(conflict) if !conflict.is_error() => CheckStatus::Duplicate,
+            _ => CheckStatus::Conflict,
+        };
+        report.patches.push(PatchCheck {
+            id: patch.id.clone(),
+            description: patch.description.clone(),
+            file: None,
+            status,
+            matches: 0,
+            error: conflict.map(|conflict| conflict.to_string()),
+        });
+    }
+    // Patched in memory by the same engine as `SteamClient::patch`, so chained patches see
+    // the output of the ones before them
+    let mut files = engine::load_files(&plan.patches)?;
+    for (patch, outcome) in engine::run(plan.patches, &mut files) {
+        let mut result = PatchCheck {
+            id: patch.id.clone(),
+            description: patch.description.clone(),
+            file: None,
+            status: CheckStatus::NotFound,
+            matches: 0,
+            error: None,
+        };
+        let holders = match outcome {
+            Outcome::Applied { file, .. } => {
+                result.status = CheckStatus::Found;
+                result.matches = 1;
+                result.file = Some(file.clone());
+                vec![file]
+            }
+            Outcome::NotFound => Vec::new(),
+            Outcome::Ambiguous { files, matches } => {
+                result.status = CheckStatus::Ambiguous;
+                result.matches = matches;
+                if let [file] = files.as_slice() {
+                    result.file = Some(file.clone());
+                } else {
+                    result.error = Some(format!("Found in several files: {:?}", files));
+                }
+                files
+            }
+            Outcome::Failed(e) => {
+                result.status = CheckStatus::Error;
+                result.error = Some(e);
+                Vec::new()
+            }
+        };
+        for path in holders {
+            if !report.files.contains(&path) {
+                report.files.push(path);
+            }
+        }
+        report.patches.push(result);
+    }
+
+    Ok(report)
+}
+
+fn print_report(report: &CheckReport) {
+    println!();
+    println!("Device: {}", report.device.as_deref().unwrap_or("unknown"));
+    if report.supported {
+        println!("Steam: {}", report.steam);
+    } else {
+        println!("Steam: {} (unsupported, outside the build range of every patch set)", report.steam);
+    }
+    println!("Files:");
+    for file in &report.files {
+        println!("  {}", file.display());
+    }
+    if !report.conflicts.is_empty() {
+        println!("Conflicts:");
+        for conflict in &report.conflicts {
+            println!("  {}", conflict);
+        }
+    }
+    println!("Patches:");
+    for patch in &report.patches {
+        let file = patch
+            .file
+            .as_ref()
+            .and_then(|file| file.file_name())
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let mut line = format!("  [{:<9}] {:<32} {}", patch.status.label(), patch.id, file);
+        if patch.status == CheckStatus::Ambiguous {
+            line.push_str(&format!(" ({} matches)", patch.matches));
+        }
+        if let Some(error) = &patch.error {
+            line.push_str(&format!(" {}", error));
+        }
+        println!("{}", line);
+    }
+
+    let applied = report.patches.iter().filter(|patch| patch.status == CheckStatus::Found).count();
+    let skipped = report
+        .patches
+        .iter()
+        .filter(|patch| matches!(patch.status, CheckStatus::Duplicate | CheckStatus::Injected))
+        .count();
+    println!("{}/{} patches would apply.", applied, report.patches.len() - skipped);
+}
+
+/// Entry point of `steam-patch check [--json]`, returns the process exit code.
+pub fn run(json: bool) -> i32 {
+    // The code paths of the check log to stderr, stdout only gets the report
+    config::initialize_config();
+    let report = check();
+
+    match report {
+        Ok(report) => {
+            if json {
+                match serde_json::to_string_pretty(&report) {
+                    Ok(output) => println!("{}", output),
+                    Err(e) => {
+                        eprintln!("Failed to serialize check report: {}", e);
+                        return 1;
+                    }
+                }
+            } else {
+                print_report(&report);
+            }
+            // No patches at all means no patch set supports the installed client
+            let all_found = report.supported && !report.patches.is_empty() && report
+                .patches
+                .iter()
+                .all(|patch| matches!(patch.status, CheckStatus::Found | CheckStatus::Duplicate | CheckStatus::Injected));
+            if all_found { 0 } else { 1 }
+        }
+        Err(e) => {
+            eprintln!("Check failed: {}", e);
+            2
+        }
+    }
+}
@@ -1,6 +1,5 @@
 use lazy_static::lazy_static;
-use serde::Deserialize;
-use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
 use std::{sync::Mutex, fs, path::PathBuf};
 
 use crate::utils::get_username;
@@ -26,6 +25,40 @@ pub struct Config {
     pub spoof_glyphs: bool,
     #[serde(default = "default_nintendo_glyphs")]
     pub nintendo_glyphs: bool,
+    #[serde(default = "default_inject_patches")]
+    pub inject_patches: bool,
+    /// Seconds the Steam UI gets to come up after patching before the patches are rolled back
+    #[serde(default = "default_rollback_timeout")]
+    pub rollback_timeout: u64,
+    /// Steam restarts right after patching before the patches are rolled back
+    #[serde(default = "default_max_steam_restarts")]
+    pub max_steam_restarts: u32,
+    #[serde(default = "default_console_log")]
+    pub console_log: ConsoleLog,
+    /// Where Steam's CEF remote debugger listens
+    #[serde(default = "default_cef_host")]
+    pub cef_host: String,
+    #[serde(default = "default_cef_port")]
+    pub cef_port: u16,
+    /// Origins of the pages allowed to call the HTTP API
+    #[serde(default = "default_allowed_origins")]
+    pub allowed_origins: Vec<String>,
+    /// Whether HTTP requests need the token in ~/steam-patch/api_token
+    #[serde(default = "default_require_token")]
+    pub require_token: bool,
+    /// Unix socket serving the HTTP API to local tools, empty to disable it
+    #[serde(default = "default_api_socket")]
+    pub api_socket: String,
+}
+
+/// Which console messages of the Steam UI end up in our log
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ConsoleLog {
+    Off,
+    /// Only messages and exceptions of the code steam-patch adds to Steam
+    Ours,
+    All,
 }
 
 // Default functions for each field
@@ -39,7 +72,33 @@ fn default_legacy_tdp() -> bool { true }
 fn default_auto_nkey_recovery() -> bool { false }
 fn default_spoof_glyphs() -> bool { true }
 fn default_nintendo_glyphs() -> bool { false }
+fn default_inject_patches() -> bool { false }
+fn default_rollback_timeout() -> u64 { 60 }
+fn default_max_steam_restarts() -> u32 { 3 }
+fn default_console_log() -> ConsoleLog { ConsoleLog::Ours }
+fn default_cef_host() -> String { String::from("localhost") }
+fn default_cef_port() -> u16 { 8080 }
+fn default_allowed_origins() -> Vec<String> { vec![String::from("https://steamloopback.host")] }
+fn default_require_token() -> bool { false }
+fn default_api_socket() -> String { String::from("/run/steam-patch.sock") }
 
+impl Config {
+    // Looks up a boolean option by name, used by patch conditions
+    pub fn flag(&self, name: &str) -> Option<bool> {
+        match name {
+            "main_enabled" => Some(self.main_enabled),
+            "tdp_control" => Some(self.tdp_control),
+            "gpu_control" => Some(self.gpu_control),
+            "mapper" => Some(self.mapper),
+            "legacy_tdp" => Some(self.legacy_tdp),
+            "auto_nkey_recovery" => Some(self.auto_nkey_recovery),
+            "spoof_glyphs" => Some(self.spoof_glyphs),
+            "nintendo_glyphs" => Some(self.nintendo_glyphs),
+            "inject_patches" => Some(self.inject_patches),
+            _ => None,
+        }
+    }
+}
 
 
 
@@ -51,13 +110,13 @@ pub fn initialize_config() -> Config {
     let mut global_config = CONFIG.lock().unwrap();
     match read_config() {
         Ok(config) => {
-            println!("CONFIG: Found config file!");
+            eprintln!("CONFIG: Found config file!");
             *global_config = Some(config.clone()); // Clone the config for local use
             // *global_config = Some(config);
             config // Return the cloned config
         }
         Err(e) => {
-            println!("CONFIG: Failed to read config: {}", e);
+            eprintln!("CONFIG: Failed to read config: {}", e);
             // Handle error, perhaps by setting default values or terminating the application
 
             let default_config = Config {
@@ -70,7 +129,16 @@ pub fn initialize_config() -> Config {
                 legacy_tdp: true,
                 auto_nkey_recovery: false,
                 spoof_glyphs: true,
-                nintendo_glyphs: false
+                nintendo_glyphs: false,
+                inject_patches: false,
+                rollback_timeout: 60,
+                max_steam_restarts: 3,
+                console_log: ConsoleLog::Ours,
+                cef_host: String::from("localhost"),
+                cef_port: 8080,
+                allowed_origins: default_allowed_origins(),
+                require_token: false,
+                api_socket: default_api_socket(),
             };
             *global_config = Some(default_config.clone());
             default_config
@@ -80,13 +148,13 @@ pub fn initialize_config() -> Config {
 fn read_config() -> Result<Config, Box<dyn std::error::Error>> {
     //Read the file into a string
     let username = get_username();
-    println!("Grabbed config user: {}", username);
+    eprintln!("Grabbed config user: {}", username);
     let config_path = PathBuf::from("/etc/steam-patch/config.toml");
-    println!("Current path {:?}", config_path);
+    eprintln!("Current path {:?}", config_path);
     let contents = fs::read_to_string(config_path)?;
     //Parse string of data into config
     let config: Config = toml::from_str(&contents)?;
-    println!("{:?}",config);
+    eprintln!("{:?}",config);
     Ok(config)
 }
 pub fn get_global_config() -> Config {
@@ -0,0 +1,96 @@
+use serde::Deserialize;
+use serde_json::Value;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::cdp::{CallFrame, Event, ExceptionDetails, StackTrace};
+use crate::config::{get_global_config, ConsoleLog};
+
+// Console output and exceptions of the Steam UI tabs, forwarded into our log. Scripts we
+// evaluate get a `sourceURL` under steam-patch/ so their output can be told apart from
+// Steam's own, code patched into the files starts its messages with MARKER instead.
+
+const SOURCE_URL: &str = "steam-patch";
+const MARKER: &str = "[steam-patch]";
+
+/// Names `script` so what it logs and throws is recognized as ours.
+pub fn tag(script: &str, name: &str) -> String {
+    format!("{}\n//# sourceURL={}/{}", script, SOURCE_URL, name)
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ConsoleCall {
+    #[serde(rename = "type")]
+    level: String,
+    #[serde(default)]
+    args: Vec<Value>,
+    #[serde(default)]
+    stack_trace: Option<StackTrace>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ExceptionThrown {
+    exception_details: ExceptionDetails,
+}
+
+/// Logs the console messages and exceptions of `tab` for as long as its session lives.
+pub fn forward(tab: String, mut events: broadcast::Receiver<Event>) {
+    tokio::spawn(async move {
+        loop {
+            match events.recv().await {
+                Ok(event) => log(&tab, &event, get_global_config().console_log),
+                Err(RecvError::Lagged(skipped)) => eprintln!("[Steam {}] {} console messages dropped", tab, skipped),
+                Err(RecvError::Closed) => break,
+            }
+        }
+    });
+}
+
+fn log(tab: &str, event: &Event, filter: ConsoleLog) {
+    if filter == ConsoleLog::Off {
+        return;
+    }
+    match event.method.as_str() {
+        "Runtime.consoleAPICalled" => {
+            let Ok(call) = serde_json::from_value::<ConsoleCall>(event.params.clone()) else {
+                return;
+            };
+            let text = call.args.iter().map(describe).collect::<Vec<_>>().join(" ");
+            let frames = call.stack_trace.map(|trace| trace.call_frames).unwrap_or_default();
+            if filter == ConsoleLog::Ours && !text.starts_with(MARKER) && !is_ours(&frames) {
+                return;
+            }
+            match call.level.as_str() {
+                "error" | "warning" | "assert" => eprintln!("[Steam {}] {}: {}", tab, call.level, text),
+                _ => println!("[Steam {}] {}: {}", tab, call.level, text),
+            }
+        }
+        "Runtime.exceptionThrown" => {
+            let Ok(thrown) = serde_json::from_value::<ExceptionThrown>(event.params.clone()) else {
+                return;
+            };
+            let details = thrown.exception_details;
+            let frames = details.stack_trace.as_ref().map(|trace| trace.call_frames.as_slice()).unwrap_or_default();
+            if filter == ConsoleLog::Ours && !details.url.starts_with(SOURCE_URL) && !is_ours(frames) {
+                return;
+            }
+            eprintln!("[Steam {}] exception: {}", tab, details);
+        }
+        _ => {}
+    }
+}
+
+// Whether any of the calls leading to the message was made by a script we evaluated
+fn is_ours(frames: &[CallFrame]) -> bool {
+    frames.iter().any(|frame| frame.url.starts_with(SOURCE_URL))
+}
+
+// Text of a logged value, objects are only described since we don't ask for previews
+fn describe(arg: &Value) -> String {
+    match arg.get("value") {
+        Some(Value::String(text)) => text.clone(),
+        Some(value) => value.to_string(),
+        None => arg["description"].as_str().or(arg["type"].as_str()).unwrap_or_default().to_string(),
+    }
+}
@@ -1,17 +1,15 @@
-use super::Device;
-use crate::config::{get_global_config, self};
+use super::{record_settings, set_mapper_state, Device, MapperState};
+use crate::config::get_global_config;
 use crate::devices::device_generic::DeviceGeneric;
 use crate::devices::Patch;
-use crate::patch::PatchFile;
 use crate::server::SettingsRequest;
+use crate::lifecycle;
 use crate::steam::SteamClient;
-use crate::{utils, main};
-use std::fs::File;
-use std::{fs, env};
-use std::process::Command;
+use crate::utils;
+use std::fs;
 use std::thread;
 use std::time::Duration;
-use std::io::{self, Write};
+use std::io;
 
 
 pub struct DeviceAlly {
@@ -40,6 +38,7 @@ impl Device for DeviceAlly {
                 match fs::read_to_string(file_path) {
                     Ok(content) if content.trim() == thermal_policy.to_string() => {
                         println!("Thermal policy set successfully.");
+                        record_settings(|settings| settings.thermal_policy = Some(thermal_policy));
                     },
                     _ => {
                         eprintln!("Failed to set thermal policy. Value could not be confirmed.");
@@ -72,18 +71,12 @@ impl Device for DeviceAlly {
     }
     //Add more patches for device specific
     fn get_patches(&self) -> Vec<Patch> {
-        let mut patches = self.device.get_patches();
-        patches.push(Patch {
-            text_to_find: String::from("this.m_rgControllers=new Map,\"undefined\"!=typeof SteamClient&&(this.m_hUnregisterControllerDigitalInput"),
-            replacement_text: String::from("this.m_rgControllers=new Map; window.HandleSystemKeyEvents = this.HandleSystemKeyEvents; \"undefined\"!=typeof SteamClient&&(this.m_hUnregisterControllerDigitalInput"),
-            destination: PatchFile::Library,
-        });
-        patches
+        self.device.load_patches("ally")
     }
 
     fn set_tdp(&self, tdp: i8) {
         // Update thermal policy
-        let thermal_policy = match tdp {
+        let _thermal_policy = match tdp {
             val if val < 12 => 2,                 // silent
             val if (12..=25).contains(&val) => 0, // performance
             _ => 1,                               // turbo
@@ -105,24 +98,39 @@ impl Device for DeviceAlly {
                 vec!["bash", "-c", command_boost],
                 vec!["bash", "-c", command_slow],
             ];
+            let mut applied = true;
             for cmd in commands {
                 println!("Command to run: {:?}",cmd);
                 match utils::run_command(&cmd) {
-                    Ok(_) => println!("Set TDP successfully!"),
-                    Err(e) => println!("Couldn't set TDP: {}", e),
+                    Ok(output) if output.status.success() => println!("Set TDP successfully!"),
+                    Ok(output) => {
+                        println!("Couldn't set TDP: {}", output.status);
+                        applied = false;
+                    }
+                    Err(e) => {
+                        println!("Couldn't set TDP: {}", e);
+                        applied = false;
+                    }
                 }
             }
-            
+            if applied {
+                record_settings(|settings| settings.tdp = Some(tdp));
+            }
+
         }
     }
 
     fn set_gpu(&self, gpu: i16) {
         //Placeholder for later implementations
         println!("New GPU clock: {}", gpu);
+        record_settings(|settings| settings.gpu_clock = Some(gpu));
     }
 
     fn get_key_mapper(&self) -> Option<tokio::task::JoinHandle<()>> {
         tokio::spawn(async move {
+            // Nothing to drive before the Steam UI is up
+            set_mapper_state(MapperState::WaitingForSteam);
+            lifecycle::wait_for_ui().await;
             let mut steam = SteamClient::new();
             steam.connect().await;
             start_mapper(steam);
@@ -139,10 +147,11 @@ pub fn pick_device() -> Option<evdev::Device> {
     for (_, device) in devices {
         let input_id = device.input_id();
 
-        if input_id.vendor() == target_vendor_id && input_id.product() == target_product_id {
-            if device.supported_keys().map_or(false, |keys| keys.contains(evdev::Key::KEY_PROG1)) {
-                return Some(device);   
-            }
+        if input_id.vendor() == target_vendor_id
+            && input_id.product() == target_product_id
+            && device.supported_keys().is_some_and(|keys| keys.contains(evdev::Key::KEY_PROG1))
+        {
+            return Some(device);
         }
     }
     None
@@ -152,8 +161,8 @@ pub fn recover_nkey() -> io::Result<()> {
     // Check if a specific USB device is not present
     println!("ROG Ally detected and USB device 0b05:1abe not present");
     
-    let command1 = format!("echo '\\_SB.PCI0.SBRG.EC0.CSEE' \"0xB7\" > /proc/acpi/call");
-    let command2 = format!("echo '\\_SB.PCI0.SBRG.EC0.CSEE' \"0xB8\" > /proc/acpi/call");
+    let command1 = "echo '\\_SB.PCI0.SBRG.EC0.CSEE' \"0xB7\" > /proc/acpi/call".to_string();
+    let command2 = "echo '\\_SB.PCI0.SBRG.EC0.CSEE' \"0xB8\" > /proc/acpi/call".to_string();
     match utils::run_command(&[&command1]) {
         Ok(_) => println!("Set 0xB7"),
         Err(e) => println!("Couldn't set 0xB7 {}", e),
@@ -169,10 +178,10 @@ pub fn recover_nkey() -> io::Result<()> {
 pub fn start_mapper(mut steam:SteamClient) -> Option<tokio::task::JoinHandle<()>> {
     let device = pick_device();
     let conf = get_global_config();
-    conf.mapper;
     if conf.mapper {
     match device {
         Some(device) => Some(tokio::spawn(async move {
+            set_mapper_state(MapperState::Running);
             if let Ok(mut events) = device.into_event_stream() {
                 loop {
                     match events.next_event().await {
@@ -205,6 +214,7 @@ pub fn start_mapper(mut steam:SteamClient) -> Option<tokio::task::JoinHandle<()>
                         },
                         Err(_) => {
                             print!("Error reading event stream, retrying in 1 second");
+                            set_mapper_state(MapperState::NoController);
                             thread::sleep(Duration::from_secs(1));
                             tokio::spawn(async move {
                                 start_mapper(steam)
@@ -217,6 +227,7 @@ pub fn start_mapper(mut steam:SteamClient) -> Option<tokio::task::JoinHandle<()>
         })),
         None => {
             println!("No Ally-specific found, retrying in 2 seconds");
+            set_mapper_state(MapperState::NoController);
 
             thread::sleep(Duration::from_secs(2));
             if conf.auto_nkey_recovery {
@@ -230,6 +241,7 @@ pub fn start_mapper(mut steam:SteamClient) -> Option<tokio::task::JoinHandle<()>
         }
     }
 } else {
+    set_mapper_state(MapperState::Off);
     None
 }
 }
@@ -1,20 +1,15 @@
-use super::Device;
-use crate::config::{get_global_config, self};
+use super::{record_settings, set_mapper_state, Device, MapperState};
+use crate::config::get_global_config;
 use crate::devices::device_generic::DeviceGeneric;
 use crate::devices::Patch;
-use crate::patch::PatchFile;
 use crate::server::SettingsRequest;
+use crate::lifecycle;
 use crate::steam::SteamClient;
-use crate::{utils, main};
 use std::fs::File as FFile;
 use std::path::Path;
-use std::{fs, env};
-use std::process::{Command, Stdio};
 use std::thread;
 use std::time::Duration as DDuration;
-use std::io::{self, Write, Read};
-use std::io::BufRead;
-use std::collections::HashMap;
+use std::io::{self, Read};
 use tokio::fs::{File, read_dir};
 use tokio::io::AsyncReadExt;
 use tokio::time::{timeout, Duration};
@@ -51,13 +46,7 @@ impl Device for DeviceGo {
     }
     //Add more patches for device specific
     fn get_patches(&self) -> Vec<Patch> {
-        let mut patches = self.device.get_patches();
-        patches.push(Patch {
-            text_to_find: String::from("this.m_rgControllers=new Map,\"undefined\"!=typeof SteamClient&&(this.m_hUnregisterControllerDigitalInput"),
-            replacement_text: String::from("this.m_rgControllers=new Map; window.HandleSystemKeyEvents = this.HandleSystemKeyEvents; \"undefined\"!=typeof SteamClient&&(this.m_hUnregisterControllerDigitalInput"),
-            destination: PatchFile::Library,
-        });
-        patches
+        self.device.load_patches("go")
     }
 
     fn set_tdp(&self, tdp: i8) {
@@ -67,10 +56,14 @@ impl Device for DeviceGo {
     fn set_gpu(&self, gpu: i16) {
         //Placeholder for later implementations
         println!("New GPU clock: {}", gpu);
+        record_settings(|settings| settings.gpu_clock = Some(gpu));
     }
 
     fn get_key_mapper(&self) -> Option<tokio::task::JoinHandle<()>> {
         tokio::spawn(async move {
+            // Nothing to drive before the Steam UI is up
+            set_mapper_state(MapperState::WaitingForSteam);
+            lifecycle::wait_for_ui().await;
             let mut steam = SteamClient::new();
             steam.connect().await;
             start_mapper(steam);
@@ -143,7 +136,7 @@ pub async fn find_active_hidraw_device() -> io::Result<Option<String>> {
             println!("Read result: {:?}", read_result);
             
             match read_result {
-                Ok(Ok(size)) if size == 64 => {
+                Ok(Ok(64)) => {
                     println!("Success at using {:?}", device_path);
                     return Ok(Some((device_path).to_string()));
                 },
@@ -164,6 +157,7 @@ pub fn start_mapper(mut steam: SteamClient) -> Option<tokio::task::JoinHandle<()
                 Ok(Some(path)) => path,
                 _ => {
                     eprintln!("No active HIDRAW device found, retrying in 2 seconds");
+                    set_mapper_state(MapperState::NoController);
                     tokio::time::sleep(Duration::from_secs(2)).await; // Asynchronous sleep
                     tokio::spawn(async move {
                         start_mapper(steam)
@@ -171,6 +165,7 @@ pub fn start_mapper(mut steam: SteamClient) -> Option<tokio::task::JoinHandle<()
                     return;
                 }
             };
+            set_mapper_state(MapperState::Running);
             let mut previous_data = Vec::new(); // Variable to keep track of prev states
             loop {
                 match read_from_hidraw(&active_device, buffer_size) {
@@ -179,13 +174,13 @@ pub fn start_mapper(mut steam: SteamClient) -> Option<tokio::task::JoinHandle<()
                         if previous_data != data && data.len() == 64{
                             // println!("Controller data: {:?}",data);
                             // println!("Data le {:?}", data.len());
-                            if(data[18] == 64){
+                            if data[18] == 64 {
                                 println!("Show QAM");
                                         steam
                                             .execute("GamepadNavTree.m_Controller.OnButtonActionInternal(true, 28, 2)")
                                             .await;
                             }
-                            if(data[18] == 128){
+                            if data[18] == 128 {
                                 if data[19] == 32 {
                                     println!("Show keyboard");
                                     steam.execute("SteamClient.URL.ExecuteSteamURL('steam://open/keyboard')").await;
@@ -209,6 +204,7 @@ pub fn start_mapper(mut steam: SteamClient) -> Option<tokio::task::JoinHandle<()
                     Err(e) => {
                         eprintln!("Failed to read from device: {}", e);                       
                         println!("Error reading event stream, retrying in 3 second");
+                        set_mapper_state(MapperState::NoController);
                         thread::sleep(DDuration::from_secs(2));
                         tokio::spawn(async move {
                             start_mapper(steam)
@@ -221,6 +217,7 @@ pub fn start_mapper(mut steam: SteamClient) -> Option<tokio::task::JoinHandle<()
         }))
     } else {
         println!("Mapper disabled");
+        set_mapper_state(MapperState::Off);
         None
     }
 }
@@ -7,10 +7,54 @@ use crate::{patch::Patch, server::SettingsRequest};
 use device_ally::DeviceAlly;
 use device_go::DeviceGo;
 use device_generic::DeviceGeneric;
+use lazy_static::lazy_static;
 use regex::Regex;
+use serde::Serialize;
 use std::fs;
+use std::sync::Mutex;
 
-use super::config::{self, Config, get_global_config};
+use super::config::get_global_config;
+
+/// Settings last applied to the device, devices are created per request so they live here
+#[derive(Serialize, Debug, Clone, Copy, Default)]
+pub struct AppliedSettings {
+    pub tdp: Option<i8>,
+    pub thermal_policy: Option<i32>,
+    pub gpu_clock: Option<i16>,
+}
+
+/// What the button mapper of the device is doing
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum MapperState {
+    /// Disabled in the config or the device has no mapper
+    Off,
+    WaitingForSteam,
+    /// The controller wasn't found or stopped answering, retrying
+    NoController,
+    Running,
+}
+
+lazy_static! {
+    static ref APPLIED: Mutex<AppliedSettings> = Mutex::new(AppliedSettings::default());
+    static ref MAPPER: Mutex<MapperState> = Mutex::new(MapperState::Off);
+}
+
+pub fn applied_settings() -> AppliedSettings {
+    *APPLIED.lock().unwrap()
+}
+
+pub fn record_settings(record: impl FnOnce(&mut AppliedSettings)) {
+    record(&mut APPLIED.lock().unwrap());
+}
+
+pub fn mapper_state() -> MapperState {
+    *MAPPER.lock().unwrap()
+}
+
+pub fn set_mapper_state(state: MapperState) {
+    *MAPPER.lock().unwrap() = state;
+}
 
 pub trait Device {
     fn update_settings(&self, request: SettingsRequest);
@@ -23,7 +67,7 @@ pub trait Device {
 
 pub fn create_device() -> Option<Box<dyn Device>> {
         let conf = get_global_config();
-        println!("Conf files loaded: {} {} {} {}", conf.gpu_control, conf.main_enabled, conf.max_tdp, conf.max_gpu);
+        eprintln!("Conf files loaded: {} {} {} {}", conf.gpu_control, conf.main_enabled, conf.max_tdp, conf.max_gpu);
         match get_device_name() {
         Some(device_name) => {
             match device_name.trim() {
@@ -80,13 +124,11 @@ pub fn create_device() -> Option<Box<dyn Device>> {
     
 }
 
-fn get_device_name() -> Option<String> {
-    let cpuinfo = fs::read_to_string("/proc/cpuinfo").expect("Unknown");
+pub fn get_device_name() -> Option<String> {
+    let cpuinfo = fs::read_to_string("/proc/cpuinfo").ok()?;
 
     let model_re = Regex::new(r"model name\s*:\s*(.*)").unwrap();
-    let model = model_re.captures_iter(&cpuinfo).next().unwrap()[1]
-        .trim()
-        .to_string();
+    let model = model_re.captures(&cpuinfo)?[1].trim().to_string();
 
     let board_vendor = match fs::read_to_string("/sys/devices/virtual/dmi/id/board_vendor") {
         Ok(str) => str.trim().to_string(),
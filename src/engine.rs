@@ -0,0 +1,423 @@
+use aho_corasick::{AhoCorasick, MatchKind};
+use regex::Regex;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::patch::{MatchMode, Patch, Substitution};
+use crate::utils::get_steamui_dir;
+
+// Applies a whole patch list to the steamui files. Every file is scanned once for all exact
+// needles, regex patches add one scan each, and the patched content is built in a single
+// allocation. Patches rewriting the output of the ones they list in `after` run in a later
+// stage, over the output of the earlier one.
+
+/// A steamui file loaded for patching
+#[derive(Debug, Clone)]
+pub struct SourceFile {
+    pub path: PathBuf,
+    /// Path relative to steamui, what the `file` of a patch is matched against
+    pub relative_path: String,
+    pub content: String,
+    pub changed: bool,
+}
+
+#[derive(Debug, Clone)]
+pub enum Outcome {
+    Applied { file: PathBuf, substitution: Substitution },
+    NotFound,
+    /// The needle matched more than once, in one or several files
+    Ambiguous { files: Vec<PathBuf>, matches: usize },
+    Failed(String),
+}
+
+// A needle found in a file, positions are in the content the stage started with
+struct Found {
+    patch: usize,
+    start: usize,
+    end: usize,
+    replacement: String,
+}
+
+/// Loads every file of steamui a patch could target. Unreadable files are left out so
+/// they are never written.
+pub fn load_files(patches: &[Patch]) -> Result<Vec<SourceFile>, String> {
+    let steamui_path = get_steamui_dir();
+    if !steamui_path.exists() {
+        return Err(format!("Steam UI path does not exist: {:?}", steamui_path));
+    }
+
+    let mut destinations: Vec<String> = patches.iter().map(|patch| patch.destination.get_regex()).collect();
+    destinations.sort();
+    destinations.dedup();
+    let any_destination = destinations
+        .iter()
+        .map(|regex| format!("(?:{})", regex))
+        .collect::<Vec<_>>()
+        .join("|");
+    let regex = Regex::new(&any_destination).map_err(|e| format!("Failed to create regex: {}", e))?;
+
+    let mut paths = Vec::new();
+    crate::patch::collect_files(&steamui_path, &steamui_path, &regex, &mut paths)?;
+    paths.sort();
+
+    Ok(paths
+        .into_iter()
+        .filter_map(|path| match fs::read_to_string(&path) {
+            Ok(content) => Some(SourceFile {
+                relative_path: relative_to(&path, &steamui_path),
+                path,
+                content,
+                changed: false,
+            }),
+            Err(e) => {
+                eprintln!("Failed to read {:?}, leaving it as is: {}", path, e);
+                None
+            }
+        })
+        .collect())
+}
+
+fn relative_to(path: &Path, root: &Path) -> String {
+    path.strip_prefix(root).unwrap_or(path).to_string_lossy().into_owned()
+}
+
+// Stage of every patch, one after the latest stage of the patches listed in its `after`
+fn stages(patches: &[Patch]) -> Vec<usize> {
+    let mut stages: Vec<usize> = Vec::with_capacity(patches.len());
+    let mut by_id: HashMap<&str, usize> = HashMap::new();
+    for patch in patches {
+        let stage = patch
+            .after
+            .iter()
+            .filter_map(|id| by_id.get(id.as_str()))
+            .map(|stage| stage + 1)
+            .max()
+            .unwrap_or(0);
+        by_id.insert(&patch.id, stage);
+        stages.push(stage);
+    }
+    stages
+}
+
+/// Applies `patches`, in plan order, to `files`. Returns the patches in the order they were
+/// applied, which is the order to keep for unpatching, with what happened to each.
+pub fn run(patches: Vec<Patch>, files: &mut [SourceFile]) -> Vec<(Patch, Outcome)> {
+    let stages = stages(&patches);
+    let mut ordered: Vec<(usize, Patch)> = stages.into_iter().zip(patches).collect();
+    ordered.sort_by_key(|(stage, _)| *stage);
+
+    let mut results: Vec<(Patch, Outcome)> = Vec::with_capacity(ordered.len());
+    let mut start = 0;
+    while start < ordered.len() {
+        let stage = ordered[start].0;
+        let end = ordered[start..]
+            .iter()
+            .position(|(other, _)| *other != stage)
+            .map_or(ordered.len(), |count| start + count);
+        let stage_patches: Vec<&Patch> = ordered[start..end].iter().map(|(_, patch)| patch).collect();
+        let outcomes = run_stage(&stage_patches, files);
+        results.extend(stage_patches.into_iter().cloned().zip(outcomes));
+        start = end;
+    }
+    results
+}
+
+fn run_stage(patches: &[&Patch], files: &mut [SourceFile]) -> Vec<Outcome> {
+    let mut outcomes: Vec<Option<Outcome>> = vec![None; patches.len()];
+
+    // Compile everything once for all the files
+    let mut destinations: Vec<Option<Regex>> = Vec::with_capacity(patches.len());
+    let mut regexes: Vec<(usize, Regex)> = Vec::new();
+    let mut needles: Vec<(usize, &str)> = Vec::new();
+    for (i, patch) in patches.iter().enumerate() {
+        match Regex::new(&patch.destination.get_regex()) {
+            Ok(destination) => destinations.push(Some(destination)),
+            Err(e) => {
+                destinations.push(None);
+                outcomes[i] = Some(Outcome::Failed(format!("Invalid file pattern in patch '{}': {}", patch.id, e)));
+                continue;
+            }
+        }
+        match patch.mode {
+            MatchMode::Exact if patch.text_to_find.is_empty() => {
+                outcomes[i] = Some(Outcome::Failed(format!("Patch '{}' has no text to find", patch.id)));
+            }
+            MatchMode::Exact => needles.push((i, &patch.text_to_find)),
+            MatchMode::Regex => match Regex::new(&patch.text_to_find) {
+                Ok(regex) => regexes.push((i, regex)),
+                Err(e) => outcomes[i] = Some(Outcome::Failed(format!("Invalid regex in patch '{}': {}", patch.id, e))),
+            },
+        }
+    }
+    let automaton = if needles.is_empty() {
+        None
+    } else {
+        match AhoCorasick::builder()
+            .match_kind(MatchKind::LeftmostFirst)
+            .build(needles.iter().map(|(_, needle)| needle))
+        {
+            Ok(automaton) => Some(automaton),
+            Err(e) => {
+                for (i, _) in &needles {
+                    outcomes[*i] = Some(Outcome::Failed(format!("Failed to build the needle matcher: {}", e)));
+                }
+                None
+            }
+        }
+    };
+
+    // Find every needle in every file it could be in
+    let mut found: Vec<Vec<Found>> = Vec::with_capacity(files.len());
+    for file in files.iter() {
+        let targets = |patch: usize| {
+            outcomes[patch].is_none()
+                && destinations[patch].as_ref().is_some_and(|destination| destination.is_match(&file.relative_path))
+        };
+        let mut in_file = Vec::new();
+        if let Some(automaton) = &automaton {
+            for found_match in automaton.find_iter(&file.content) {
+                let patch = needles[found_match.pattern().as_usize()].0;
+                if targets(patch) {
+                    in_file.push(Found {
+                        patch,
+                        start: found_match.start(),
+                        end: found_match.end(),
+                        replacement: patches[patch].replacement_text.clone(),
+                    });
+                }
+            }
+        }
+        for (patch, regex) in regexes.iter().filter(|(patch, _)| targets(*patch)) {
+            for captures in regex.captures_iter(&file.content) {
+                let whole = captures.get(0).expect("Group 0 is always present");
+                let mut replacement = String::new();
+                captures.expand(&patches[*patch].replacement_text, &mut replacement);
+                in_file.push(Found { patch: *patch, start: whole.start(), end: whole.end(), replacement });
+            }
+        }
+        found.push(in_file);
+    }
+
+    // Each needle has to be found exactly once
+    for (i, outcome) in outcomes.iter_mut().enumerate() {
+        if outcome.is_some() {
+            continue;
+        }
+        let holders: Vec<(usize, usize)> = found
+            .iter()
+            .enumerate()
+            .map(|(file, in_file)| (file, in_file.iter().filter(|found| found.patch == i).count()))
+            .filter(|(_, count)| *count > 0)
+            .collect();
+        *outcome = match holders.as_slice() {
+            [] => Some(Outcome::NotFound),
+            [(_, 1)] => None,
+            _ => Some(Outcome::Ambiguous {
+                files: holders.iter().map(|(file, _)| files[*file].path.clone()).collect(),
+                matches: holders.iter().map(|(_, count)| count).sum(),
+            }),
+        };
+    }
+
+    for (file, mut in_file) in files.iter_mut().zip(found) {
+        in_file.retain(|found| outcomes[found.patch].is_none());
+        in_file.sort_by_key(|found| found.start);
+
+        // Needles of different patches can still run into each other, the first one wins
+        let mut last_end = 0;
+        in_file.retain(|found| {
+            if found.start < last_end {
+                outcomes[found.patch] = Some(Outcome::Failed(format!(
+                    "Patch '{}' overlaps with another patch in {:?}",
+                    patches[found.patch].id, file.path
+                )));
+                return false;
+            }
+            last_end = found.end;
+            true
+        });
+        if in_file.is_empty() {
+            continue;
+        }
+
+        let size = in_file
+            .iter()
+            .fold(file.content.len(), |size, found| size + found.replacement.len() - (found.end - found.start));
+        let mut patched = String::with_capacity(size);
+        let mut last = 0;
+        for (n, found) in in_file.iter().enumerate() {
+            patched.push_str(&file.content[last..found.start]);
+            patched.push_str(&found.replacement);
+            last = found.end;
+
+            // Where the replacement sits once the patches applied before this one are in,
+            // so unpatching in reverse order finds every replacement at its offset
+            let offset = in_file[..n]
+                .iter()
+                .filter(|earlier| earlier.patch < found.patch)
+                .fold(found.start, |offset, earlier| {
+                    offset + earlier.replacement.len() - (earlier.end - earlier.start)
+                });
+            outcomes[found.patch] = Some(Outcome::Applied {
+                file: file.path.clone(),
+                substitution: Substitution {
+                    original: file.content[found.start..found.end].to_string(),
+                    replacement: found.replacement.clone(),
+                    offset: Some(offset),
+                },
+            });
+        }
+        patched.push_str(&file.content[last..]);
+        file.content = patched;
+        file.changed = true;
+    }
+
+    outcomes.into_iter().map(|outcome| outcome.unwrap_or(Outcome::NotFound)).collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::patch::AppliedPatch;
+    use crate::patch_set::PatchSet;
+    use crate::planner;
+
+    const ORIGINAL: &str = "B1;A1;foo();twice();twice();abcdef";
+
+    fn patches(toml: &str) -> Vec<Patch> {
+        toml::from_str::<PatchSet>(toml).expect("Test patches should parse").patches
+    }
+
+    fn chunk(content: &str) -> Vec<SourceFile> {
+        vec![SourceFile {
+            path: PathBuf::from("chunk~1.js"),
+            relative_path: "chunk~1.js".to_string(),
+            content: content.to_string(),
+            changed: false,
+        }]
+    }
+
+    // Reverts the applied patches newest first like `SteamClient::unpatch`
+    fn unpatch(results: &[(Patch, Outcome)], content: &mut String) {
+        for (patch, outcome) in results.iter().rev() {
+            if let Outcome::Applied { file, substitution } = outcome {
+                let applied = AppliedPatch { id: patch.id.clone(), file: file.clone(), substitutions: vec![substitution.clone()] };
+                assert!(applied.revert(content), "'{}' couldn't be reverted", patch.id);
+            }
+        }
+    }
+
+    #[test]
+    fn two_patches_in_one_file_revert_at_their_offsets() {
+        let mut files = chunk(ORIGINAL);
+        let results = run(
+            patches(
+                r#"
+                [[patch]]
+                id = "a"
+                find = "A1"
+                replace = "XXXX"
+
+                [[patch]]
+                id = "b"
+                find = "B1"
+                replace = "XXXX"
+                "#,
+            ),
+            &mut files,
+        );
+        assert!(files[0].content.starts_with("XXXX;XXXX;"));
+
+        // `a` is applied before `b`, its offset ignores the text `b` adds in front of it
+        let offsets: Vec<Option<usize>> = results
+            .iter()
+            .map(|(_, outcome)| match outcome {
+                Outcome::Applied { substitution, .. } => substitution.offset,
+                other => panic!("Patch wasn't applied: {:?}", other),
+            })
+            .collect();
+        assert_eq!(offsets, vec![Some(3), Some(0)]);
+
+        unpatch(&results, &mut files[0].content);
+        assert_eq!(files[0].content, ORIGINAL);
+    }
+
+    #[test]
+    fn patches_run_after_the_ones_they_rewrite() {
+        let mut files = chunk(ORIGINAL);
+        // Ordered by the planner like every patch list the engine gets
+        let results = run(
+            planner::plan(patches(
+                r#"
+                [[patch]]
+                id = "inner"
+                find = "bar()"
+                replace = "baz()"
+                after = ["outer"]
+
+                [[patch]]
+                id = "outer"
+                find = "foo()"
+                replace = "foo(bar())"
+                "#,
+            ))
+            .patches,
+            &mut files,
+        );
+        let order: Vec<&str> = results.iter().map(|(patch, _)| patch.id.as_str()).collect();
+        assert_eq!(order, vec!["outer", "inner"]);
+        assert!(files[0].content.contains(";foo(baz());"));
+
+        unpatch(&results, &mut files[0].content);
+        assert_eq!(files[0].content, ORIGINAL);
+    }
+
+    #[test]
+    fn needles_found_twice_are_ambiguous() {
+        let mut files = chunk(ORIGINAL);
+        let results = run(
+            patches(
+                r#"
+                [[patch]]
+                id = "twice"
+                find = "twice()"
+                replace = "once()"
+                "#,
+            ),
+            &mut files,
+        );
+        assert!(matches!(&results[0].1, Outcome::Ambiguous { files, matches: 2 } if files.len() == 1));
+        assert_eq!(files[0].content, ORIGINAL);
+        assert!(!files[0].changed);
+    }
+
+    #[test]
+    fn overlapping_needles_apply_the_first_one() {
+        let mut files = chunk(ORIGINAL);
+        let results = run(
+            patches(
+                r#"
+                [[patch]]
+                id = "first"
+                find = "abcd"
+                replace = "ABCD"
+
+                [[patch]]
+                id = "second"
+                mode = "regex"
+                find = "c(d)ef"
+                replace = "C${1}EF"
+                "#,
+            ),
+            &mut files,
+        );
+        assert!(matches!(results[0].1, Outcome::Applied { .. }));
+        assert!(matches!(&results[1].1, Outcome::Failed(e) if e.contains("overlaps")));
+        assert!(files[0].content.ends_with(";ABCDef"));
+
+        unpatch(&results, &mut files[0].content);
+        assert_eq!(files[0].content, ORIGINAL);
+    }
+}
@@ -0,0 +1,164 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::config::Config;
+use crate::engine::{self, Outcome, SourceFile};
+use crate::patch::{AppliedPatch, Patch, PatchVars};
+use crate::patch_set::{select_patches, PatchSet};
+use crate::planner;
+
+// Runs the built-in patch set against the steamui files stored in tests/fixtures, for
+// every device and every combination of the config flags the patches depend on. The
+// stored files are hand-written, see tests/fixtures/README.md
+
+const DEVICES: &[&str] = &["generic", "ally", "go"];
+
+fn get_fixtures_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures")
+}
+
+// Files of a build keyed by their path relative to the build directory
+fn read_build(root: &Path, dir: &Path, files: &mut BTreeMap<String, String>) {
+    for entry in fs::read_dir(dir).expect("Fixture directory should be readable").flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            read_build(root, &path, files);
+            continue;
+        }
+        let relative_path = path.strip_prefix(root).unwrap_or(&path).to_string_lossy().into_owned();
+        let content = fs::read_to_string(&path).expect("Fixture should be readable");
+        files.insert(relative_path, content);
+    }
+}
+
+// Every combination of the config flags named in patch conditions, with a label
+fn config_combinations(patches: &[Patch]) -> Vec<(String, Config)> {
+    let mut flags: Vec<&str> = patches
+        .iter()
+        .flat_map(|patch| &patch.when.config)
+        .map(|flag| flag.trim_start_matches('!'))
+        .collect();
+    flags.sort();
+    flags.dedup();
+
+    (0..1u32 << flags.len())
+        .map(|bits| {
+            let values: Vec<String> = flags
+                .iter()
+                .enumerate()
+                .map(|(i, flag)| format!("{} = {}", flag, bits & (1 << i) != 0))
+                .collect();
+            let config = toml::from_str(&values.join("\n")).expect("Config flags should parse");
+            (values.join(", "), config)
+        })
+        .collect()
+}
+
+// Patches then unpatches a build in memory with the engine `SteamClient` uses, returns what
+// went wrong and adds the ids of the applied patches to `applied_ids`
+fn run(files: &BTreeMap<String, String>, patches: Vec<Patch>, applied_ids: &mut HashSet<String>) -> Vec<String> {
+    let mut failures = Vec::new();
+    let mut applied: Vec<AppliedPatch> = Vec::new();
+
+    let plan = planner::plan(patches.into_iter().filter(|patch| !patch.is_injected()).collect());
+    for conflict in plan.conflicts.iter().filter(|conflict| conflict.is_error()) {
+        failures.push(conflict.to_string());
+    }
+
+    let mut sources: Vec<SourceFile> = files
+        .iter()
+        .map(|(path, content)| SourceFile {
+            path: PathBuf::from(path),
+            relative_path: path.clone(),
+            content: content.clone(),
+            changed: false,
+        })
+        .collect();
+    for (patch, outcome) in engine::run(plan.patches, &mut sources) {
+        match outcome {
+            Outcome::Applied { file, substitution } => {
+                applied_ids.insert(patch.id.clone());
+                applied.push(AppliedPatch { id: patch.id, file, substitutions: vec![substitution] });
+            }
+            Outcome::NotFound => failures.push(format!("'{}' not found", patch.id)),
+            Outcome::Ambiguous { files, matches } => {
+                failures.push(format!("'{}' found {} times in {:?}", patch.id, matches, files))
+            }
+            Outcome::Failed(e) => failures.push(e),
+        }
+    }
+
+    // Newest first like `SteamClient::unpatch` without a pristine copy
+    let mut patched: BTreeMap<String, String> = sources
+        .into_iter()
+        .map(|source| (source.relative_path, source.content))
+        .collect();
+    for patch in applied.iter().rev() {
+        let content = patched
+            .get_mut(&*patch.file.to_string_lossy())
+            .expect("Applied patch file is a fixture file");
+        if !patch.revert(content) {
+            failures.push(format!("'{}' couldn't be reverted", patch.id));
+        }
+    }
+    for (path, content) in &patched {
+        if content != &files[path] {
+            failures.push(format!("{} differs from the original after unpatching", path));
+        }
+    }
+    failures
+}
+
+#[test]
+fn builtin_patches_are_valid() {
+    for patch in PatchSet::builtin().patches {
+        assert!(!patch.id.is_empty(), "Built-in patches need an id");
+        assert_eq!(patch.validate(), Ok(()));
+    }
+}
+
+#[test]
+fn builtin_patches_round_trip_on_fixture_files() {
+    let builtin = PatchSet::builtin().patches;
+    let vars = PatchVars { max_tdp: 30, min_gpu: 800, max_gpu: 2700, api_token: String::new() };
+    let combinations = config_combinations(&builtin);
+
+    let mut builds: Vec<PathBuf> = fs::read_dir(get_fixtures_dir())
+        .expect("Fixtures directory should exist")
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.is_dir())
+        .collect();
+    builds.sort();
+    assert!(!builds.is_empty(), "No Steam UI fixtures in {:?}", get_fixtures_dir());
+
+    let mut failures = Vec::new();
+    for build in &builds {
+        let build_name = build.file_name().unwrap_or_default().to_string_lossy();
+        let mut files = BTreeMap::new();
+        read_build(build, build, &mut files);
+
+        let mut applied_ids = HashSet::new();
+        for device in DEVICES {
+            for (flags, config) in &combinations {
+                let patches = select_patches(builtin.clone(), device, config, &vars);
+                for failure in run(&files, patches, &mut applied_ids) {
+                    failures.push(format!("{} [{}; {}]: {}", build_name, device, flags, failure));
+                }
+            }
+        }
+
+        // A patch no combination applies is dead weight or has a broken condition
+        for patch in builtin.iter().filter(|patch| !patch.is_injected()) {
+            if !applied_ids.contains(&patch.id) {
+                failures.push(format!("{}: '{}' never applied", build_name, patch.id));
+            }
+        }
+    }
+
+    // Report every broken patch of every build at once
+    failures.sort();
+    failures.dedup();
+    assert!(failures.is_empty(), "{} failures:\n{}", failures.len(), failures.join("\n"));
+}
@@ -0,0 +1,153 @@
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use std::sync::Mutex;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::cdp::Event;
+use crate::lifecycle::{self, SteamState, Transition};
+use crate::process_watcher::{Process, ProcessEvent, ProcessWatcher};
+use crate::steam::SteamClient;
+
+// Tracks the game Steam is running. Steam starts every game under a `reaper` process with
+// `SteamLaunch AppId=<id>` on its command line, the Steam UI is followed for when there is
+// none to be seen, ie. when the game runs in a sandbox of its own. Once the UI is up it
+// reports every game starting and stopping through a debugger binding, nothing is polled.
+
+// App id of the running game according to the Steam UI
+const RUNNING_APP_QUERY: &str = "Number(window.Router?.MainRunningApp?.appid) || null";
+// Function the debugger adds to the Steam UI, its calls come back as events
+const BINDING: &str = "steamPatchAppLifetime";
+// Forwards the app lifetime notifications of Steam to BINDING, replacing the registration
+// made before in the same document
+const FOLLOW_SCRIPT: &str = "window.steamPatchAppLifetimeRegistration?.unregister(); \
+    window.steamPatchAppLifetimeRegistration = SteamClient.GameSessions.RegisterForAppLifetimeNotifications(\
+    (notification) => steamPatchAppLifetime(JSON.stringify(notification))); undefined";
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum GameEvent {
+    GameStarted { app_id: u32 },
+    GameStopped { app_id: u32 },
+}
+
+lazy_static! {
+    static ref CURRENT: Mutex<Option<u32>> = Mutex::new(None);
+    static ref EVENTS: broadcast::Sender<GameEvent> = broadcast::channel(16).0;
+}
+
+/// App id of the running game.
+pub fn current() -> Option<u32> {
+    *CURRENT.lock().unwrap()
+}
+
+/// Game starts and stops from now on.
+#[allow(dead_code)]
+pub fn subscribe() -> broadcast::Receiver<GameEvent> {
+    EVENTS.subscribe()
+}
+
+pub fn spawn() -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut reapers = ProcessWatcher::new(|process| process.name == "reaper" && app_id(process).is_some());
+        let mut transitions = lifecycle::subscribe();
+        // Pid and app id of every reaper in the order they started, the last one is the running game
+        let mut games: Vec<(u32, u32)> = Vec::new();
+        let mut steam = SteamClient::new();
+        let mut notifications = steam.subscribe();
+        // Running game according to the Steam UI, used when no reaper is seen
+        let mut ui_game = if lifecycle::current().is_ui_up() { follow_ui(&mut steam).await } else { None };
+        loop {
+            tokio::select! {
+                Some(event) = reapers.next() => match event {
+                    ProcessEvent::Started(process) => {
+                        if let Some(app_id) = app_id(&process) {
+                            games.push((process.pid, app_id));
+                        }
+                    }
+                    ProcessEvent::Exited(process) => {
+                        games.retain(|(pid, _)| *pid != process.pid);
+                    }
+                },
+                transition = transitions.recv() => match transition {
+                    Ok(Transition { to, .. }) if to.is_ui_up() => ui_game = follow_ui(&mut steam).await,
+                    Ok(Transition { to: SteamState::SteamNotRunning, .. }) => ui_game = None,
+                    Ok(_) => continue,
+                    // Missed transitions, catch up with the current state
+                    Err(RecvError::Lagged(_)) if lifecycle::current().is_ui_up() => ui_game = follow_ui(&mut steam).await,
+                    Err(RecvError::Lagged(_)) => ui_game = None,
+                    Err(RecvError::Closed) => return,
+                },
+                notification = notifications.recv() => match notification {
+                    Ok(event) => match app_lifetime(&event) {
+                        Some(AppLifetime { app_id, running: true }) => ui_game = Some(app_id),
+                        Some(AppLifetime { app_id, running: false }) if ui_game == Some(app_id) => ui_game = None,
+                        _ => continue,
+                    },
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => return,
+                },
+            }
+            update(games.last().map(|(_, app_id)| *app_id).or(ui_game));
+        }
+    })
+}
+
+// Has the Steam UI report the games it starts and stops to BINDING, which lasts until the
+// UI reloads, and returns the game running now
+async fn follow_ui(steam: &mut SteamClient) -> Option<u32> {
+    let registered = match steam.call("Runtime.addBinding", serde_json::json!({ "name": BINDING })).await {
+        Ok(_) => steam.query::<()>(FOLLOW_SCRIPT).await,
+        Err(e) => Err(e),
+    };
+    if let Err(e) = registered {
+        eprintln!("Couldn't follow games in the Steam UI: {}", e);
+    }
+    steam.query::<Option<u32>>(RUNNING_APP_QUERY).await.ok().flatten()
+}
+
+#[derive(Deserialize)]
+struct AppLifetime {
+    #[serde(rename = "unAppID")]
+    app_id: u32,
+    #[serde(rename = "bRunning")]
+    running: bool,
+}
+
+// App lifetime notification carried by a call of BINDING
+fn app_lifetime(event: &Event) -> Option<AppLifetime> {
+    if event.method != "Runtime.bindingCalled" || event.params["name"] != BINDING {
+        return None;
+    }
+    serde_json::from_str(event.params["payload"].as_str()?).ok()
+}
+
+fn update(running: Option<u32>) {
+    let previous = std::mem::replace(&mut *CURRENT.lock().unwrap(), running);
+    if previous == running {
+        return;
+    }
+    // Nobody listening is fine
+    if let Some(app_id) = previous {
+        println!("Game stopped: {}", app_id);
+        let _ = EVENTS.send(GameEvent::GameStopped { app_id });
+    }
+    if let Some(app_id) = running {
+        println!("Game started: {}", app_id);
+        let _ = EVENTS.send(GameEvent::GameStarted { app_id });
+    }
+}
+
+fn app_id(reaper: &Process) -> Option<u32> {
+    parse_reaper(&reaper.cmdline()?)
+}
+
+// App id from the command line of a reaper, its arguments are separated by NUL
+fn parse_reaper(cmdline: &[u8]) -> Option<u32> {
+    let args: Vec<&[u8]> = cmdline.split(|byte| *byte == 0).collect();
+    if !args.first()?.ends_with(b"reaper") || !args.contains(&&b"SteamLaunch"[..]) {
+        return None;
+    }
+    args.iter()
+        .find_map(|arg| arg.strip_prefix(b"AppId="))
+        .and_then(|app_id| std::str::from_utf8(app_id).ok()?.parse().ok())
+}
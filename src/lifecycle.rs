@@ -0,0 +1,110 @@
+use lazy_static::lazy_static;
+use serde::Serialize;
+use serde_json::Value;
+use std::sync::Mutex;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+// State of the Steam client as seen by `SteamClient::watch`, the only one changing it.
+// Every change is published so other tasks can follow Steam without polling it themselves.
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SteamState {
+    /// The CEF debugger doesn't answer
+    SteamNotRunning,
+    /// The debugger answers but SharedJSContext isn't up yet
+    CefUp,
+    /// SharedJSContext is up, without our patches
+    UiReady,
+    /// SharedJSContext is up with the patches applied and injected
+    Patched,
+    /// The UI was reloaded to pick up new patches and hasn't settled yet
+    Reloading,
+}
+
+impl SteamState {
+    /// Whether scripts can run in the Steam UI
+    pub fn is_ui_up(self) -> bool {
+        matches!(self, SteamState::UiReady | SteamState::Patched)
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Copy)]
+pub struct Transition {
+    pub from: SteamState,
+    pub to: SteamState,
+}
+
+// The state with what is worked out from it until the next transition
+struct Current {
+    state: SteamState,
+    // Transitions so far, tells a stale computation from a fresh one
+    transitions: u64,
+    patches: Option<Value>,
+}
+
+lazy_static! {
+    static ref STATE: Mutex<Current> = Mutex::new(Current {
+        state: SteamState::SteamNotRunning,
+        transitions: 0,
+        patches: None,
+    });
+    static ref TRANSITIONS: broadcast::Sender<Transition> = broadcast::channel(16).0;
+}
+
+pub fn current() -> SteamState {
+    STATE.lock().unwrap().state
+}
+
+/// Moves to `to` and publishes the transition, staying in the same state publishes nothing.
+pub fn set(to: SteamState) {
+    let from = {
+        let mut current = STATE.lock().unwrap();
+        let from = std::mem::replace(&mut current.state, to);
+        if from != to {
+            current.transitions += 1;
+            current.patches = None;
+        }
+        from
+    };
+    if from != to {
+        println!("Steam state: {:?} -> {:?}", from, to);
+        // Nobody listening is fine
+        let _ = TRANSITIONS.send(Transition { from, to });
+    }
+}
+
+/// Status of the patches as `compute` reports it, computed once per transition since it
+/// hashes every patched file.
+pub fn patch_status(compute: impl FnOnce() -> Value) -> Value {
+    let transitions = {
+        let current = STATE.lock().unwrap();
+        if let Some(patches) = &current.patches {
+            return patches.clone();
+        }
+        current.transitions
+    };
+    let patches = compute();
+    let mut current = STATE.lock().unwrap();
+    // Steam moved on meanwhile, the next call works it out again
+    if current.transitions == transitions {
+        current.patches = Some(patches.clone());
+    }
+    patches
+}
+
+/// Transitions from now on.
+pub fn subscribe() -> broadcast::Receiver<Transition> {
+    TRANSITIONS.subscribe()
+}
+
+/// Waits until scripts can run in the Steam UI.
+pub async fn wait_for_ui() {
+    let mut transitions = subscribe();
+    while !current().is_ui_up() {
+        match transitions.recv().await {
+            Ok(_) | Err(RecvError::Lagged(_)) => continue,
+            Err(RecvError::Closed) => return,
+        }
+    }
+}
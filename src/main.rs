@@ -2,26 +2,48 @@ use config::get_global_config;
 
 use crate::devices::create_device;
 
-#[macro_use]
-extern crate lazy_static;
-
+mod backup;
+mod cdp;
+mod check;
 mod devices;
+mod engine;
+mod lifecycle;
+#[cfg(test)]
+mod fixture_tests;
+mod game;
+mod manifest;
+#[cfg(test)]
+mod mock_cef;
 mod patch;
+mod patch_set;
+mod planner;
+mod process_watcher;
+mod quarantine;
 mod server;
 mod steam;
+#[cfg(test)]
+mod steam_tests;
+mod steam_version;
+mod update_watcher;
 mod utils;
 mod config;
+mod console;
 
 #[tokio::main]
 async fn main() {
+    // Dry run, reports which patches would apply without touching Steam
+    if utils::has_arg("check") {
+        std::process::exit(check::run(utils::has_arg("--json")));
+    }
+
     // Initialize the config by reading it and storing it in the global CONFIG
     let _ = config::initialize_config();
 
-
     let config = get_global_config();
-    if config.main_enabled == true {
+    if config.main_enabled {
         let mut tasks = vec![];
         tasks.push(tokio::spawn(server::run()));
+        tasks.push(game::spawn());
 
         if let Some(device) = create_device() {
             if let Some(mapper) = device.get_key_mapper() {
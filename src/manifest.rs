@@ -0,0 +1,348 @@
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::Error;
+use std::path::PathBuf;
+
+use crate::backup::hash;
+use crate::config::{get_global_config, Config};
+use crate::patch::{AppliedPatch, MatchMode, Patch, PatchFile, Substitution};
+use crate::utils::get_steam_patch_dir;
+
+/// Record of the last patch run, kept in ~/steam-patch/manifest.json
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PatchManifest {
+    pub steam_patch_version: String,
+    /// Hash of the patch definitions that were applied
+    pub patch_set: String,
+    /// Hash of every file we wrote
+    pub files: HashMap<PathBuf, String>,
+    pub patches: Vec<PatchRecord>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PatchRecord {
+    pub id: String,
+    pub file: Option<PathBuf>,
+    pub hash_before: Option<String>,
+    pub hash_after: Option<String>,
+    pub status: PatchStatus,
+    #[serde(default)]
+    pub substitutions: Vec<Substitution>,
+    /// Config flags the conditions of the patch read, with the values it was selected with
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub config: BTreeMap<String, bool>,
+}
+
+impl PatchRecord {
+    pub fn new(patch: &Patch, config: &Config, status: PatchStatus) -> PatchRecord {
+        PatchRecord {
+            id: patch.id.clone(),
+            file: None,
+            hash_before: None,
+            hash_after: None,
+            status,
+            substitutions: Vec::new(),
+            config: patch
+                .when
+                .flags()
+                .filter_map(|flag| Some((flag.to_string(), config.flag(flag)?)))
+                .collect(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum PatchStatus {
+    Applied,
+    NotFound,
+    /// Runs over the CEF debugger instead of being written to a file
+    Injected,
+    /// Left out on purpose, ie. a duplicate of another patch
+    Skipped(String),
+    Failed(String),
+}
+
+pub fn fingerprint(patches: &[Patch]) -> String {
+    hash(&serde_json::to_vec(patches).unwrap_or_default())
+}
+
+fn get_manifest_path() -> PathBuf {
+    get_steam_patch_dir().join("manifest.json")
+}
+
+// Marker file used before the manifest existed
+fn get_legacy_marker_path() -> PathBuf {
+    get_steam_patch_dir().join("patched")
+}
+
+// Patches of the releases that only left the empty marker behind, in the order they were
+// applied: id, config flag they needed, file, text they replaced and text they wrote.
+// `{number}` stands for the device limit they wrote in.
+const LEGACY_PATCHES: &[(&str, Option<&str>, PatchFile, &str, &str)] = &[
+    (
+        "max_tdp",
+        None,
+        PatchFile::Chunk,
+        "return[o,t,n,e=>a((()=>p.Get().SetTDPLimit(e)))",
+        "return[o,t,{number},e=>a((()=>p.Get().SetTDPLimit(e)))",
+    ),
+    (
+        "max_gpu",
+        None,
+        PatchFile::Chunk,
+        "return[o,t,n,e=>a((()=>p.Get().SetGPUPerformanceManualMhz(e)))",
+        "return[o,t,{number},e=>a((()=>p.Get().SetGPUPerformanceManualMhz(e)))",
+    ),
+    (
+        "settings_forwarding",
+        None,
+        PatchFile::Chunk,
+        "const t=c.Hm.deserializeBinary(e).toObject();Object.keys(t)",
+        "const t=c.Hm.deserializeBinary(e).toObject(); console.log(t); fetch(`http://localhost:1338/update_settings`, { method: 'POST',  headers: {'Content-Type': 'application/json'}, body: JSON.stringify(t.settings)}); Object.keys(t)",
+    ),
+    (
+        "native_resolution",
+        None,
+        PatchFile::Chunk,
+        "DownloadComplete_Title\"),r=Ue(n,t.data.appid());const s=(0,x.Q2)();",
+        "DownloadComplete_Title\"),r=Ue(n,t.data.appid()); SteamClient.Apps.GetResolutionOverrideForApp(t.data.appid()).then(res => res === \"Default\" && SteamClient.Apps.SetAppResolutionOverride(t.data.appid(), \"Native\")); const s=(0,x.Q2)();",
+    ),
+    (
+        "spoof_glyphs.xbox_logo",
+        Some("spoof_glyphs"),
+        PatchFile::Chunk,
+        r#"e="/steaminputglyphs/xbox_button_logo.svg""#,
+        r#"return l.createElement(A.ActionGlyph, { button: n, size: A.EActionGlyphSize.Medium})"#,
+    ),
+    (
+        "spoof_glyphs.ps4_logo",
+        Some("spoof_glyphs"),
+        PatchFile::Chunk,
+        r#"e="/steaminputglyphs/ps4_button_logo.svg""#,
+        r#"return l.createElement(A.ActionGlyph, { button: n, size: A.EActionGlyphSize.Medium})"#,
+    ),
+    (
+        "spoof_glyphs.ps_x",
+        Some("spoof_glyphs"),
+        PatchFile::Chunk,
+        r#""/steaminputglyphs/ps_color_button_x.svg""#,
+        r#""/steaminputglyphs/shared_button_a.svg""#,
+    ),
+    (
+        "spoof_glyphs.ps_square",
+        Some("spoof_glyphs"),
+        PatchFile::Chunk,
+        r#""/steaminputglyphs/ps_color_button_square.svg""#,
+        r#""/steaminputglyphs/shared_button_x.svg""#,
+    ),
+    (
+        "spoof_glyphs.ps_triangle",
+        Some("spoof_glyphs"),
+        PatchFile::Chunk,
+        r#""/steaminputglyphs/ps_color_button_triangle.svg""#,
+        r#""/steaminputglyphs/shared_button_y.svg""#,
+    ),
+    (
+        "spoof_glyphs.ps_circle",
+        Some("spoof_glyphs"),
+        PatchFile::Chunk,
+        r#""/steaminputglyphs/ps_color_button_circle.svg""#,
+        r#""/steaminputglyphs/shared_button_b.svg""#,
+    ),
+    (
+        "spoof_glyphs.ps4_options",
+        Some("spoof_glyphs"),
+        PatchFile::Chunk,
+        r#""/steaminputglyphs/ps4_button_options.svg""#,
+        r#""/steaminputglyphs/sd_button_menu.svg""#,
+    ),
+    (
+        "nintendo_glyphs.xbox_logo",
+        Some("nintendo_glyphs"),
+        PatchFile::Chunk,
+        r#"e="/steaminputglyphs/xbox_button_logo.svg""#,
+        r#"return l.createElement(A.ActionGlyph, { button: n, size: A.EActionGlyphSize.Medium})"#,
+    ),
+    (
+        "nintendo_glyphs.ps4_logo",
+        Some("nintendo_glyphs"),
+        PatchFile::Chunk,
+        r#"e="/steaminputglyphs/ps4_button_logo.svg""#,
+        r#"return l.createElement(A.ActionGlyph, { button: n, size: A.EActionGlyphSize.Medium})"#,
+    ),
+    (
+        "nintendo_glyphs.a",
+        Some("nintendo_glyphs"),
+        PatchFile::Chunk,
+        r#""/steaminputglyphs/shared_color_button_a.svg""#,
+        r#""/steaminputglyphs/shared_button_b.svg""#,
+    ),
+    (
+        "nintendo_glyphs.b",
+        Some("nintendo_glyphs"),
+        PatchFile::Chunk,
+        r#""/steaminputglyphs/shared_color_button_b.svg""#,
+        r#""/steaminputglyphs/shared_color_button_a.svg""#,
+    ),
+    (
+        "nintendo_glyphs.y",
+        Some("nintendo_glyphs"),
+        PatchFile::Chunk,
+        r#""/steaminputglyphs/shared_color_button_y.svg""#,
+        r#""/steaminputglyphs/shared_button_x.svg""#,
+    ),
+    (
+        "nintendo_glyphs.x",
+        Some("nintendo_glyphs"),
+        PatchFile::Chunk,
+        r#""/steaminputglyphs/shared_color_button_x.svg""#,
+        r#""/steaminputglyphs/shared_color_button_y.svg""#,
+    ),
+    (
+        "nintendo_glyphs.ps4_options",
+        Some("nintendo_glyphs"),
+        PatchFile::Chunk,
+        r#""/steaminputglyphs/ps4_button_options.svg""#,
+        r#""/steaminputglyphs/sd_button_menu.svg""#,
+    ),
+    (
+        "system_key_events",
+        None,
+        PatchFile::Library,
+        "this.m_rgControllers=new Map,\"undefined\"!=typeof SteamClient&&(this.m_hUnregisterControllerDigitalInput",
+        "this.m_rgControllers=new Map; window.HandleSystemKeyEvents = this.HandleSystemKeyEvents; \"undefined\"!=typeof SteamClient&&(this.m_hUnregisterControllerDigitalInput",
+    ),
+];
+
+// What the legacy patches left in the files, with the limits they wrote in read back.
+// Whatever isn't found anymore was never applied or Steam replaced the file since.
+fn legacy_patches(config: &Config) -> Vec<AppliedPatch> {
+    let mut files = HashMap::new();
+    LEGACY_PATCHES
+        .iter()
+        .filter(|(_, flag, ..)| flag.is_none_or(|flag| config.flag(flag) == Some(true)))
+        .filter_map(|(id, _, file, original, written)| {
+            let needle = regex::escape(written).replace(r"\{number\}", r"\d+");
+            let reverse = Patch {
+                id: id.to_string(),
+                text_to_find: needle.clone(),
+                destination: file.clone(),
+                mode: MatchMode::Regex,
+                ..Patch::default()
+            };
+            let path = reverse.locate(&mut files).ok()?;
+            let replacement = Regex::new(&needle).ok()?.find(&files[&path])?.as_str().to_string();
+            Some(AppliedPatch {
+                id: id.to_string(),
+                file: path,
+                substitutions: vec![Substitution { original: original.to_string(), replacement, offset: None }],
+            })
+        })
+        .collect()
+}
+
+impl PatchManifest {
+    pub fn new(patches: &[Patch]) -> PatchManifest {
+        PatchManifest {
+            steam_patch_version: env!("CARGO_PKG_VERSION").to_string(),
+            patch_set: fingerprint(patches),
+            files: HashMap::new(),
+            patches: Vec::new(),
+        }
+    }
+
+    /// Reads the manifest of the last run. An old `patched` marker is turned into a manifest
+    /// that never matches, holding what those releases wrote so it gets reverted before
+    /// the files are backed up and patched again.
+    pub fn load() -> Option<PatchManifest> {
+        if let Ok(content) = fs::read_to_string(get_manifest_path()) {
+            match serde_json::from_str(&content) {
+                Ok(manifest) => return Some(manifest),
+                Err(e) => eprintln!("Failed to parse patch manifest: {}", e),
+            }
+        }
+
+        let legacy_path = get_legacy_marker_path();
+        if !legacy_path.exists() {
+            return None;
+        }
+        println!("Found legacy patch marker {:?}", legacy_path);
+        let mut manifest = PatchManifest::new(&[]);
+        manifest.patch_set = String::new();
+        manifest.patches = legacy_patches(&get_global_config())
+            .into_iter()
+            .map(|applied| PatchRecord {
+                id: applied.id,
+                file: Some(applied.file),
+                hash_before: None,
+                hash_after: None,
+                status: PatchStatus::Applied,
+                substitutions: applied.substitutions,
+                config: BTreeMap::new(),
+            })
+            .collect();
+        Some(manifest)
+    }
+
+    pub fn save(&self) -> Result<(), Error> {
+        let path = get_manifest_path();
+        println!("Writing patch manifest to {:?}", path);
+        fs::write(&path, serde_json::to_string_pretty(self)?)?;
+        let _ = fs::remove_file(get_legacy_marker_path());
+        Ok(())
+    }
+
+    pub fn remove() -> Result<(), Error> {
+        let _ = fs::remove_file(get_legacy_marker_path());
+        match fs::remove_file(get_manifest_path()) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    /// True when the manifest was written for `patches` with `config`
+    /// and the patched files are still the ones we wrote.
+    pub fn matches(&self, patches: &[Patch], config: &Config) -> bool {
+        if self.patch_set != fingerprint(patches) {
+            println!("Patch set changed since the last patch run.");
+            return false;
+        }
+        // Only the flags a patch was selected by matter to it
+        for record in &self.patches {
+            for (flag, value) in &record.config {
+                if config.flag(flag) != Some(*value) {
+                    println!("Config flag {} changed since the last patch run.", flag);
+                    return false;
+                }
+            }
+        }
+        for (path, expected) in &self.files {
+            match fs::read(path) {
+                Ok(content) if &hash(&content) == expected => continue,
+                _ => {
+                    println!("{:?} changed since the last patch run.", path);
+                    return false;
+                }
+            }
+        }
+        true
+    }
+
+    /// Patches that were applied, in the order they were applied.
+    pub fn applied(&self) -> Vec<AppliedPatch> {
+        self.patches
+            .iter()
+            .filter(|record| record.status == PatchStatus::Applied)
+            .filter_map(|record| {
+                Some(AppliedPatch {
+                    id: record.id.clone(),
+                    file: record.file.clone()?,
+                    substitutions: record.substitutions.clone(),
+                })
+            })
+            .collect()
+    }
+}
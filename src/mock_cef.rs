@@ -0,0 +1,192 @@
+use serde_json::Value;
+use std::collections::{HashMap, VecDeque};
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use tokio_tungstenite::tungstenite::{accept, Message};
+
+// Fake Steam CEF debugger for tests. Serves the `/json` tab list and a websocket per tab
+// that records every command and answers with scripted replies, `{}` by default.
+
+/// A command received over a tab's websocket
+#[derive(Debug, Clone)]
+pub struct Call {
+    pub tab: String,
+    pub method: String,
+    pub params: Value,
+}
+
+#[derive(Debug, Clone)]
+pub enum Reply {
+    Result(Value),
+    /// Protocol error with its code and message
+    Error(i64, String),
+    /// `Runtime.evaluate` result of a script that threw `text`
+    Exception(String),
+    /// Closes the websocket instead of answering
+    Disconnect,
+}
+
+#[derive(Default)]
+struct State {
+    up: bool,
+    tabs: Vec<String>,
+    calls: Vec<Call>,
+    replies: HashMap<String, VecDeque<Reply>>,
+}
+
+#[derive(Clone)]
+pub struct MockCef {
+    port: u16,
+    state: Arc<Mutex<State>>,
+}
+
+impl MockCef {
+    /// Starts answering on a free port with the tabs titled `tabs`.
+    pub fn start(tabs: &[&str]) -> MockCef {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind mock CEF server");
+        let mock = MockCef {
+            port: listener.local_addr().expect("Mock CEF server has no address").port(),
+            state: Arc::new(Mutex::new(State {
+                up: true,
+                tabs: tabs.iter().map(|tab| tab.to_string()).collect(),
+                ..State::default()
+            })),
+        };
+        let server = mock.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let server = server.clone();
+                thread::spawn(move || server.handle(stream));
+            }
+        });
+        mock
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// While down, connections are dropped like when Steam isn't running.
+    pub fn set_up(&self, up: bool) {
+        self.state.lock().unwrap().up = up;
+    }
+
+    pub fn set_tabs(&self, tabs: &[&str]) {
+        self.state.lock().unwrap().tabs = tabs.iter().map(|tab| tab.to_string()).collect();
+    }
+
+    /// Queues the reply to the next `method` command.
+    pub fn reply(&self, method: &str, reply: Reply) {
+        let mut state = self.state.lock().unwrap();
+        state.replies.entry(method.to_string()).or_default().push_back(reply);
+    }
+
+    /// Commands received so far with `method`.
+    pub fn calls(&self, method: &str) -> Vec<Call> {
+        let state = self.state.lock().unwrap();
+        state.calls.iter().filter(|call| call.method == method).cloned().collect()
+    }
+
+    fn handle(&self, mut stream: TcpStream) {
+        if !self.state.lock().unwrap().up {
+            return;
+        }
+        // The request line tells the tab list from a websocket
+        let mut head = [0u8; 512];
+        let Ok(read) = stream.peek(&mut head) else {
+            return;
+        };
+        let request = String::from_utf8_lossy(&head[..read]).to_string();
+        let path = request.split_whitespace().nth(1).unwrap_or_default().to_string();
+        match path.strip_prefix("/devtools/page/") {
+            Some(tab) => self.serve_tab(stream, tab.to_string()),
+            None => {
+                let _ = stream.read(&mut head);
+                let body = self.tab_list().to_string();
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                );
+                let _ = stream.write_all(response.as_bytes());
+            }
+        }
+    }
+
+    fn tab_list(&self) -> Value {
+        let state = self.state.lock().unwrap();
+        let tabs: Vec<Value> = state
+            .tabs
+            .iter()
+            .enumerate()
+            .map(|(id, title)| {
+                serde_json::json!({
+                    "id": id.to_string(),
+                    "title": title,
+                    "type": "page",
+                    "url": format!("https://steamloopback.host/{}", title),
+                    "webSocketDebuggerUrl": format!("ws://127.0.0.1:{}/devtools/page/{}", self.port, id),
+                })
+            })
+            .collect();
+        Value::Array(tabs)
+    }
+
+    fn serve_tab(&self, stream: TcpStream, id: String) {
+        let Ok(mut socket) = accept(stream) else {
+            return;
+        };
+        let tab = {
+            let state = self.state.lock().unwrap();
+            id.parse::<usize>().ok().and_then(|id| state.tabs.get(id).cloned()).unwrap_or(id)
+        };
+        // Like CEF, a reload is followed by its load event once the Page domain is enabled
+        let mut page_enabled = false;
+        while let Ok(message) = socket.read() {
+            let Message::Text(text) = message else {
+                continue;
+            };
+            let Ok(mut request) = serde_json::from_str::<Value>(&text) else {
+                continue;
+            };
+            let method = request["method"].as_str().unwrap_or_default().to_string();
+            let reply = {
+                let mut state = self.state.lock().unwrap();
+                state.calls.push(Call {
+                    tab: tab.clone(),
+                    method: method.clone(),
+                    params: request["params"].take(),
+                });
+                state.replies.get_mut(&method).and_then(VecDeque::pop_front)
+            };
+            let id = request["id"].clone();
+            let response = match reply.unwrap_or(Reply::Result(serde_json::json!({}))) {
+                Reply::Result(result) => serde_json::json!({ "id": id, "result": result }),
+                Reply::Error(code, message) => {
+                    serde_json::json!({ "id": id, "error": { "code": code, "message": message } })
+                }
+                Reply::Exception(text) => serde_json::json!({
+                    "id": id,
+                    "result": {
+                        "result": { "type": "object", "subtype": "error" },
+                        "exceptionDetails": { "text": text, "lineNumber": 1, "columnNumber": 2 },
+                    },
+                }),
+                Reply::Disconnect => return,
+            };
+            let reloaded = method == "Page.reload" && response.get("error").is_none();
+            page_enabled |= method == "Page.enable";
+            if socket.send(Message::Text(response.to_string())).is_err() {
+                return;
+            }
+            if reloaded && page_enabled {
+                let event = serde_json::json!({ "method": "Page.loadEventFired", "params": { "timestamp": 0 } });
+                if socket.send(Message::Text(event.to_string())).is_err() {
+                    return;
+                }
+            }
+        }
+    }
+}
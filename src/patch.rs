@@ -1,65 +1,300 @@
 use regex::Regex;
+use serde::{Deserialize, Serialize};
 
-use crate::utils::get_username;
-use std::{fs, path::PathBuf};
+use crate::cdp::TabSelector;
+use crate::config::Config;
+use crate::utils::get_steamui_dir;
+use std::collections::HashMap;
+use std::{fs, path::{Path, PathBuf}};
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Patch {
+    #[serde(default)]
+    pub id: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub kind: PatchKind,
+    #[serde(default, rename = "find")]
     pub text_to_find: String,
+    #[serde(default, rename = "replace")]
     pub replacement_text: String,
+    #[serde(default, rename = "file")]
     pub destination: PatchFile,
+    #[serde(default)]
+    pub mode: MatchMode,
+    /// JavaScript run by injected patches
+    #[serde(default)]
+    pub script: String,
+    /// Tab the script of an injected patch runs in, SharedJSContext by default
+    #[serde(default, skip_serializing_if = "TabSelector::is_default")]
+    pub tab: TabSelector,
+    #[serde(default)]
+    pub when: PatchCondition,
+    /// Ids of patches whose output this patch is meant to rewrite
+    #[serde(default)]
+    pub after: Vec<String>,
 }
 
-#[derive(Debug)]
+/// Whether a patch rewrites a steamui file or injects its script over the CEF debugger.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PatchKind {
+    #[default]
+    File,
+    /// `Runtime.evaluate` every time the Steam UI comes up
+    Evaluate,
+    /// `Page.addScriptToEvaluateOnNewDocument`, runs before Steam's own code on every reload
+    NewDocument,
+}
+
+/// How `find` is matched against the file.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MatchMode {
+    #[default]
+    Exact,
+    /// `find` is a regex, `replace` can reuse capture groups with `$1` or `${name}`
+    Regex,
+}
+
+/// Concrete text swapped in a file, recorded so unpatching never needs the regex again.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Substitution {
+    pub original: String,
+    pub replacement: String,
+    /// Where the replacement starts in the content right after the patch was applied
+    #[serde(default)]
+    pub offset: Option<usize>,
+}
+
+/// Result of a patch applied to a file.
+#[derive(Debug, Clone)]
+pub struct AppliedPatch {
+    pub id: String,
+    pub file: PathBuf,
+    pub substitutions: Vec<Substitution>,
+}
+
+/// Files of the steamui directory a patch can target, the patch goes to the one holding its needle.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum PatchFile {
+    #[default]
     Chunk,
     Library,
+    /// Glob over paths relative to steamui, ie. `css/*.css`, `**` crosses directories
+    Glob(String),
+    /// Regex over paths relative to steamui
+    Regex(String),
 }
 
-impl PatchFile {
-    pub fn get_regex(&self) -> &str {
-        match self {
-            PatchFile::Chunk => "^chunk",
-            PatchFile::Library => "^library",
+/// Restricts a patch to some devices and config flags, an empty list matches everything.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PatchCondition {
+    #[serde(default)]
+    pub devices: Vec<String>,
+    /// Config flag names that must be enabled, prefix with `!` for flags that must be disabled.
+    #[serde(default)]
+    pub config: Vec<String>,
+}
+
+/// Device values and the API token substituted into `{placeholders}` of the replacement text.
+#[derive(Debug, Clone)]
+pub struct PatchVars {
+    pub max_tdp: i8,
+    pub min_gpu: i16,
+    pub max_gpu: i16,
+    /// Token the HTTP API wants, empty when it doesn't
+    pub api_token: String,
+}
+
+fn render_text(text: &str, vars: &PatchVars) -> String {
+    text.replace("{max_tdp}", &vars.max_tdp.to_string())
+        .replace("{min_gpu}", &vars.min_gpu.to_string())
+        .replace("{max_gpu}", &vars.max_gpu.to_string())
+        .replace("{api_token}", &vars.api_token)
+}
+
+impl Patch {
+    pub fn render(mut self, vars: &PatchVars) -> Patch {
+        self.replacement_text = render_text(&self.replacement_text, vars);
+        self.script = render_text(&self.script, vars);
+        self
+    }
+
+    pub fn is_injected(&self) -> bool {
+        self.kind != PatchKind::File
+    }
+
+    /// Checks the fields the kind of patch needs are set.
+    pub fn validate(&self) -> Result<(), String> {
+        match self.kind {
+            PatchKind::File if self.text_to_find.is_empty() => {
+                Err(format!("Patch '{}' has no text to find", self.id))
+            }
+            PatchKind::Evaluate | PatchKind::NewDocument if self.script.is_empty() => {
+                Err(format!("Injected patch '{}' has no script", self.id))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Number of places `find` matches in `content`, used to check patches without applying them.
+    pub fn count_matches(&self, content: &str) -> Result<usize, String> {
+        match self.mode {
+            MatchMode::Exact => Ok(content.matches(self.text_to_find.as_str()).count()),
+            MatchMode::Regex => Regex::new(&self.text_to_find)
+                .map(|regex| regex.find_iter(content).count())
+                .map_err(|e| format!("Invalid regex in patch '{}': {}", self.id, e)),
+        }
+    }
+}
+
+impl AppliedPatch {
+    /// Reverts the substitutions in `content`, newest first so chained ones unwind in order.
+    /// Returns false when a replacement couldn't be found anymore.
+    pub fn revert(&self, content: &mut String) -> bool {
+        let mut reverted = true;
+        for substitution in self.substitutions.iter().rev() {
+            // The recorded offset tells apart patches that insert the same text
+            let at_offset = substitution.offset.filter(|&offset| {
+                content.get(offset..offset + substitution.replacement.len()) == Some(substitution.replacement.as_str())
+            });
+            if let Some(offset) = at_offset {
+                content.replace_range(offset..offset + substitution.replacement.len(), &substitution.original);
+            } else if content.contains(&substitution.replacement) {
+                *content = content.replacen(&substitution.replacement, &substitution.original, 1);
+            } else {
+                println!("Failed to unpatch: '{}'", substitution.replacement);
+                reverted = false;
+            }
         }
+        reverted
+    }
+}
+
+impl PatchCondition {
+    /// Names of the config flags the condition reads
+    pub fn flags(&self) -> impl Iterator<Item = &str> {
+        self.config.iter().map(|flag| flag.trim_start_matches('!'))
+    }
+
+    pub fn matches(&self, device: &str, config: &Config) -> bool {
+        if !self.devices.is_empty() && !self.devices.iter().any(|d| d == device) {
+            return false;
+        }
+
+        self.config.iter().all(|flag| {
+            let (name, expected) = match flag.strip_prefix('!') {
+                Some(name) => (name, false),
+                None => (flag.as_str(), true),
+            };
+            match config.flag(name) {
+                Some(value) => value == expected,
+                None => {
+                    eprintln!("Unknown config flag in patch condition: {}", name);
+                    false
+                }
+            }
+        })
     }
 }
 
 impl PatchFile {
-    pub fn get_file(&self) -> Result<Option<PathBuf>, String> {
-        let username = get_username();
-        let steamui_path = dirs::home_dir()
-            .ok_or("Home directory not found")?
-            .join(format!("/home/{}/.local/share/Steam/steamui", username));
-        
+    pub fn get_regex(&self) -> String {
+        match self {
+            PatchFile::Chunk => "^chunk".to_string(),
+            PatchFile::Library => "^library".to_string(),
+            PatchFile::Glob(glob) => glob_to_regex(glob),
+            PatchFile::Regex(regex) => regex.clone(),
+        }
+    }
+
+    /// Every file of steamui the destination matches.
+    pub fn get_files(&self) -> Result<Vec<PathBuf>, String> {
+        let steamui_path = get_steamui_dir();
         if !steamui_path.exists() {
-            println!("Steam UI path does not exist: {:?}", steamui_path);
+            return Err(format!("Steam UI path does not exist: {:?}", steamui_path));
         }
-    
-        let regex = Regex::new(self.get_regex()).map_err(|e| format!("Failed to create regex: {}", e))?;
-        
+
+        let regex = Regex::new(&self.get_regex()).map_err(|e| format!("Failed to create regex: {}", e))?;
+
         let mut matching_files = Vec::new();
+        collect_files(&steamui_path, &steamui_path, &regex, &mut matching_files)?;
+        matching_files.sort();
+        Ok(matching_files)
+    }
+}
+
+pub fn collect_files(root: &Path, dir: &Path, regex: &Regex, matching_files: &mut Vec<PathBuf>) -> Result<(), String> {
+    let entries = fs::read_dir(dir).map_err(|e| format!("Failed to read Steam UI directory {:?}: {}", dir, e))?;
+    for entry in entries {
+        let entry = entry.map_err(|e| format!("Failed to read an entry in the Steam UI directory: {}",e))?;
+        let path = entry.path();
+        // Symlinks are not followed, a link back up the tree would never end
+        let file_type = entry.file_type().map_err(|e| format!("Failed to read the type of {:?}: {}", path, e))?;
+        if file_type.is_dir() {
+            collect_files(root, &path, regex, matching_files)?;
+            continue;
+        }
+        let relative_path = path.strip_prefix(root).unwrap_or(&path).to_string_lossy().into_owned();
+        if regex.is_match(&relative_path) {
+            matching_files.push(path);
+        }
+    }
+    Ok(())
+}
 
-        let entries = fs::read_dir(&steamui_path).map_err(|e| format!("Failed to read Steam UI directory: {}",e))?;
-        for entry in entries {
-            let entry = entry.map_err(|e| format!("Failed to read an entry in the Steam UI directory: {}",e))?;
-            let file_name = entry.file_name().into_string().map_err(|_| "Failed to convert OsString to String")?;
-            if regex.is_match(&file_name) {
-                matching_files.push(entry.path());
-                // If there's more than one match, no need to continue
-                if matching_files.len() > 1 {
-                    println!("Expected one matching file, found multiple.");
-                    return Ok(Some(entry.path()));
+fn glob_to_regex(glob: &str) -> String {
+    let mut regex = String::from("^");
+    let mut chars = glob.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                regex.push_str(".*");
+            }
+            '*' => regex.push_str("[^/]*"),
+            '?' => regex.push_str("[^/]"),
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    regex.push('$');
+    regex
+}
+
+impl Patch {
+    /// Files of the destination that contain the needle. `files` caches their content so
+    /// patches applied in memory are taken into account.
+    pub fn find_in(&self, files: &mut HashMap<PathBuf, String>) -> Result<Vec<PathBuf>, String> {
+        let mut holders = Vec::new();
+        for path in self.destination.get_files()? {
+            if !files.contains_key(&path) {
+                match fs::read_to_string(&path) {
+                    Ok(content) => {
+                        files.insert(path.clone(), content);
+                    }
+                    Err(e) => {
+                        eprintln!("Failed to read {:?}, skipping it: {}", path, e);
+                        continue;
+                    }
                 }
             }
+            if self.count_matches(&files[&path])? > 0 {
+                holders.push(path);
+            }
         }
-    
-        if matching_files.len() == 1 {
-            Ok(matching_files.pop())
-        } else {
-            println!("Expected one matching file, found {}: {:?}", matching_files.len(), matching_files);
-            Ok(None)
+        Ok(holders)
+    }
+
+    /// The one file of the destination that contains the needle.
+    pub fn locate(&self, files: &mut HashMap<PathBuf, String>) -> Result<PathBuf, String> {
+        let mut holders = self.find_in(files)?;
+        match holders.len() {
+            0 => Err(format!("No file matching {:?} contains the needle of patch '{}'", self.destination, self.id)),
+            1 => Ok(holders.remove(0)),
+            _ => Err(format!("Several files contain the needle of patch '{}': {:?}", self.id, holders)),
         }
     }
-    
 }
@@ -0,0 +1,156 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::config::{get_global_config, Config};
+use crate::patch::{Patch, PatchVars};
+use crate::steam_version::{self, SteamRequirement, SteamVersion};
+use crate::utils::get_steam_patch_dir;
+
+// Fallback used when no patch set file is installed
+const BUILTIN_PATCH_SET: &str = include_str!("../patches/default.toml");
+
+pub const SYSTEM_PATCH_DIR: &str = "/etc/steam-patch/patches";
+
+#[derive(Deserialize, Debug, Default)]
+pub struct PatchSet {
+    /// Steam client builds the set is for
+    #[serde(default)]
+    pub steam: SteamRequirement,
+    #[serde(default, rename = "patch")]
+    pub patches: Vec<Patch>,
+}
+
+impl PatchSet {
+    pub fn builtin() -> PatchSet {
+        toml::from_str(BUILTIN_PATCH_SET).expect("Built-in patch set should be valid")
+    }
+
+    pub fn from_file(path: &Path) -> Result<PatchSet, String> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read patch set {:?}: {}", path, e))?;
+
+        let set: PatchSet = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => toml::from_str(&contents)
+                .map_err(|e| format!("Failed to parse patch set {:?}: {}", path, e))?,
+            Some("json") => serde_json::from_str(&contents)
+                .map_err(|e| format!("Failed to parse patch set {:?}: {}", path, e))?,
+            _ => return Err(format!("Unsupported patch set format: {:?}", path)),
+        };
+        for patch in &set.patches {
+            patch.validate().map_err(|e| format!("Invalid patch set {:?}: {}", path, e))?;
+        }
+        Ok(set)
+    }
+}
+
+pub fn get_user_patch_dir() -> PathBuf {
+    get_steam_patch_dir().join("patches")
+}
+
+// Every *.toml and *.json file of a directory, sorted by file name
+fn list_patch_files(dir: &Path) -> Vec<PathBuf> {
+    let mut paths: Vec<PathBuf> = match fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                matches!(path.extension().and_then(|ext| ext.to_str()), Some("toml") | Some("json"))
+            })
+            .collect(),
+        Err(_) => return Vec::new(),
+    };
+    paths.sort();
+    paths
+}
+
+// Reads the patch sets of a directory written for `version`
+fn read_patch_dir(dir: &Path, version: &SteamVersion) -> Vec<PatchSet> {
+    list_patch_files(dir)
+        .iter()
+        .filter_map(|path| match PatchSet::from_file(path) {
+            Ok(set) if !set.steam.matches(version) => {
+                eprintln!("Skipping patch set {:?}, it is for another Steam client", path);
+                None
+            }
+            Ok(set) => {
+                eprintln!("Loaded patch set {:?}", path);
+                Some(set)
+            }
+            Err(e) => {
+                eprintln!("{}", e);
+                None
+            }
+        })
+        .collect()
+}
+
+/// Loads the system and user patch sets written for the installed Steam client, patches
+/// from the user directory override system ones with the same id. Falls back to the
+/// built-in set when none is installed for this client, whatever build it was written for.
+pub fn load_patch_sets() -> Vec<Patch> {
+    let version = steam_version::detect();
+    eprintln!("Detected {}", version);
+
+    let mut sets = read_patch_dir(Path::new(SYSTEM_PATCH_DIR), &version);
+    sets.extend(read_patch_dir(&get_user_patch_dir(), &version));
+
+    let builtin = PatchSet::builtin();
+    let supported = is_covered(&sets, &version) || is_covered(std::slice::from_ref(&builtin), &version);
+    if sets.is_empty() {
+        // The built-in set is the fallback even for builds it wasn't written for
+        eprintln!("No patch set found, using the built-in patches.");
+        sets.push(builtin);
+    }
+    if !supported {
+        eprintln!("{} is outside the build range of every patch set, it is unsupported and the patches may not apply.", version);
+    }
+
+    let mut patches: Vec<Patch> = Vec::new();
+    let mut index: HashMap<String, usize> = HashMap::new();
+    for patch in sets.into_iter().flat_map(|set| set.patches) {
+        if patch.id.is_empty() {
+            patches.push(patch);
+            continue;
+        }
+        match index.get(&patch.id) {
+            Some(&i) => patches[i] = patch,
+            None => {
+                index.insert(patch.id.clone(), patches.len());
+                patches.push(patch);
+            }
+        }
+    }
+    patches
+}
+
+// True when one of `sets` names a build range holding the build of `version`
+fn is_covered(sets: &[PatchSet], version: &SteamVersion) -> bool {
+    sets.iter().any(|set| set.steam.has_build_range() && set.steam.matches(version))
+}
+
+/// True when the installed or built-in patch sets were written for the build of `version`.
+/// Sets without a build range are applied to any build but don't make it a supported one.
+pub fn is_supported(version: &SteamVersion) -> bool {
+    let mut sets: Vec<PatchSet> = [Path::new(SYSTEM_PATCH_DIR).to_path_buf(), get_user_patch_dir()]
+        .iter()
+        .flat_map(|dir| list_patch_files(dir))
+        .filter_map(|path| PatchSet::from_file(&path).ok())
+        .collect();
+    sets.push(PatchSet::builtin());
+    is_covered(&sets, version)
+}
+
+/// Patches that apply to `device` with the current config, placeholders filled in.
+pub fn get_patches_for(device: &str, vars: &PatchVars) -> Vec<Patch> {
+    select_patches(load_patch_sets(), device, &get_global_config(), vars)
+}
+
+/// Patches of `patches` that apply to `device` with `conf`, placeholders filled in.
+pub fn select_patches(patches: Vec<Patch>, device: &str, conf: &Config, vars: &PatchVars) -> Vec<Patch> {
+    patches
+        .into_iter()
+        .filter(|patch| patch.when.matches(device, conf))
+        .map(|patch| patch.render(vars))
+        .collect()
+}
@@ -0,0 +1,291 @@
+use serde::Serialize;
+use std::fmt;
+
+use crate::patch::{MatchMode, Patch};
+
+// Orders a patch list before it is applied and catches patches that step on each other,
+// ie. two feature sets rewriting the same needle.
+
+#[derive(Debug)]
+pub struct Plan {
+    /// Patches to apply, in order
+    pub patches: Vec<Patch>,
+    /// Patches left out of the plan, with the name their conflicts refer to them by
+    pub rejected: Vec<(String, Patch)>,
+    pub conflicts: Vec<Conflict>,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ConflictKind {
+    /// Same needle and same replacement, the copy is dropped
+    Duplicate,
+    /// Same needle with another replacement
+    SameNeedle,
+    /// One needle contains the other or ends where the other starts, whichever runs first
+    /// breaks the second one
+    Overlap,
+    /// The needle matches the output of another patch that isn't listed in `after`,
+    /// the patch is moved before it so it never rewrites that output
+    Chain,
+    /// The ordering constraints loop
+    Cycle,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Conflict {
+    pub kind: ConflictKind,
+    /// Patch the resolution was applied to
+    pub patch: String,
+    pub other: String,
+}
+
+impl Conflict {
+    /// Whether the patch was left out because of this conflict
+    pub fn is_rejection(&self) -> bool {
+        self.kind != ConflictKind::Chain
+    }
+
+    /// Whether the patch set is broken, duplicates and reordering are resolved harmlessly
+    pub fn is_error(&self) -> bool {
+        !matches!(self.kind, ConflictKind::Duplicate | ConflictKind::Chain)
+    }
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            ConflictKind::Duplicate => write!(f, "'{}' duplicates '{}', dropped", self.patch, self.other),
+            ConflictKind::SameNeedle => write!(f, "'{}' rewrites the same text as '{}' differently, rejected", self.patch, self.other),
+            ConflictKind::Overlap => write!(f, "'{}' overlaps with '{}', rejected", self.patch, self.other),
+            ConflictKind::Chain => write!(f, "'{}' matches the output of '{}', applying it first", self.patch, self.other),
+            ConflictKind::Cycle => write!(f, "'{}' has a circular order with '{}', rejected", self.patch, self.other),
+        }
+    }
+}
+
+// Needles and replacements are only compared as plain text, a regex can't be run against
+// another patch's regex or replacement template. Regex patches are left to the engine,
+// which rejects a match overlapping one made before it.
+fn both_exact(a: &Patch, b: &Patch) -> bool {
+    a.mode == MatchMode::Exact && b.mode == MatchMode::Exact
+}
+
+// True when a text holding both needles can have them share characters: one contains the
+// other, or the end of one is the start of the other. Sharing only punctuation is left out,
+// needles often start and end on the same quotes or brackets.
+fn overlaps(a: &str, b: &str) -> bool {
+    a.contains(b) || b.contains(a) || straddles(a, b) || straddles(b, a)
+}
+
+// A suffix of `a` holding a word character is a prefix of `b`
+fn straddles(a: &str, b: &str) -> bool {
+    (1..a.len().min(b.len())).any(|k| {
+        b.is_char_boundary(k) && a.ends_with(&b[..k]) && b[..k].chars().any(|c| c.is_alphanumeric() || c == '_' || c == '$')
+    })
+}
+
+fn same_target(a: &Patch, b: &Patch) -> bool {
+    a.destination == b.destination
+}
+
+fn name(patch: &Patch, index: usize) -> String {
+    if patch.id.is_empty() {
+        format!("#{}", index)
+    } else {
+        patch.id.clone()
+    }
+}
+
+pub fn plan(patches: Vec<Patch>) -> Plan {
+    let names: Vec<String> = patches.iter().enumerate().map(|(i, patch)| name(patch, i)).collect();
+    let mut conflicts = Vec::new();
+    let mut rejected = vec![false; patches.len()];
+
+    // Needles that collide, the first patch in the list wins
+    for b in 0..patches.len() {
+        for a in 0..b {
+            if rejected[a] || !same_target(&patches[a], &patches[b]) {
+                continue;
+            }
+            let (pa, pb) = (&patches[a], &patches[b]);
+            let kind = if pa.text_to_find == pb.text_to_find && pa.mode == pb.mode {
+                if pa.replacement_text == pb.replacement_text {
+                    ConflictKind::Duplicate
+                } else {
+                    ConflictKind::SameNeedle
+                }
+            } else if both_exact(pa, pb) && overlaps(&pa.text_to_find, &pb.text_to_find) {
+                ConflictKind::Overlap
+            } else {
+                continue;
+            };
+            conflicts.push(Conflict { kind, patch: names[b].clone(), other: names[a].clone() });
+            rejected[b] = true;
+            break;
+        }
+    }
+
+    // Ordering constraints, (before, after)
+    let mut edges: Vec<(usize, usize)> = Vec::new();
+    for b in 0..patches.len() {
+        if rejected[b] {
+            continue;
+        }
+        for a in 0..patches.len() {
+            if a == b || rejected[a] {
+                continue;
+            }
+            if patches[b].after.contains(&names[a]) {
+                edges.push((a, b));
+            } else if same_target(&patches[a], &patches[b])
+                && both_exact(&patches[a], &patches[b])
+                && patches[a].replacement_text.contains(&patches[b].text_to_find)
+            {
+                // Only worth reporting when it changes the order
+                if b > a {
+                    conflicts.push(Conflict { kind: ConflictKind::Chain, patch: names[b].clone(), other: names[a].clone() });
+                }
+                edges.push((b, a));
+            }
+        }
+    }
+
+    // Topological sort keeping the original order where there is no constraint
+    let mut order = Vec::new();
+    let mut placed = rejected.clone();
+    while placed.iter().any(|&p| !p) {
+        let ready = (0..patches.len()).find(|&i| {
+            !placed[i] && !edges.iter().any(|&(before, after)| after == i && !placed[before])
+        });
+        match ready {
+            Some(i) => {
+                placed[i] = true;
+                order.push(i);
+            }
+            None => {
+                // Break the cycle by rejecting the last patch still waiting
+                let last = (0..patches.len()).rev().find(|&i| !placed[i]).expect("A patch is waiting");
+                let other = edges
+                    .iter()
+                    .find(|&&(before, after)| after == last && !placed[before])
+                    .map(|&(before, _)| names[before].clone())
+                    .unwrap_or_default();
+                conflicts.push(Conflict { kind: ConflictKind::Cycle, patch: names[last].clone(), other });
+                placed[last] = true;
+                rejected[last] = true;
+            }
+        }
+    }
+
+    let mut slots: Vec<Option<Patch>> = patches.into_iter().map(Some).collect();
+    let planned = order
+        .iter()
+        .filter(|&&i| !rejected[i])
+        .filter_map(|&i| slots[i].take())
+        .collect();
+    let rejected = names.into_iter().zip(slots).filter_map(|(name, slot)| Some((name, slot?))).collect();
+
+    Plan { patches: planned, rejected, conflicts }
+}
+
+impl Plan {
+    /// Why the rejected patch named `name` was left out
+    pub fn rejection_of(&self, name: &str) -> Option<&Conflict> {
+        self.conflicts
+            .iter()
+            .find(|conflict| conflict.is_rejection() && conflict.patch == name)
+    }
+
+    pub fn log_conflicts(&self) {
+        for conflict in &self.conflicts {
+            if conflict.is_error() {
+                eprintln!("Patch conflict: {}", conflict);
+            } else {
+                eprintln!("Patch order: {}", conflict);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::patch_set::PatchSet;
+
+    fn patches(toml: &str) -> Vec<Patch> {
+        toml::from_str::<PatchSet>(toml).expect("Test patches should parse").patches
+    }
+
+    #[test]
+    fn needles_sharing_a_suffix_and_prefix_overlap() {
+        let plan = plan(patches(
+            r#"
+            [[patch]]
+            id = "a"
+            find = "SetTDPLimit(e)"
+            replace = "SetTDPLimit(e*2)"
+
+            [[patch]]
+            id = "b"
+            find = "Limit(e)))"
+            replace = "Limit(e)));"
+
+            [[patch]]
+            id = "c"
+            find = '"/a.svg"'
+            replace = '"/b.svg"'
+
+            [[patch]]
+            id = "d"
+            find = '"/c.svg"'
+            replace = '"/d.svg"'
+            "#,
+        ));
+        assert_eq!(plan.conflicts.len(), 1);
+        assert_eq!(plan.conflicts[0].kind, ConflictKind::Overlap);
+        assert_eq!(plan.rejection_of("b").map(|conflict| conflict.other.as_str()), Some("a"));
+        // Needles only sharing a quote can sit side by side
+        assert_eq!(plan.patches.len(), 3);
+    }
+
+    #[test]
+    fn regex_patches_are_not_compared_as_text() {
+        // The replacement template of `a` holds the needle of `b`, its output never does
+        let plan = plan(patches(
+            r#"
+            [[patch]]
+            id = "a"
+            mode = "regex"
+            find = '(\w)=>x'
+            replace = '${1}=>y'
+
+            [[patch]]
+            id = "b"
+            find = "${1}=>y"
+            replace = "z"
+            "#,
+        ));
+        assert!(plan.conflicts.is_empty(), "{:?}", plan.conflicts);
+        assert_eq!(plan.patches.len(), 2);
+    }
+
+    #[test]
+    fn unnamed_patches_are_rejected_by_index() {
+        let plan = plan(patches(
+            r#"
+            [[patch]]
+            find = "abc"
+            replace = "abd"
+
+            [[patch]]
+            find = "abc"
+            replace = "abe"
+            "#,
+        ));
+        assert_eq!(plan.rejected.len(), 1);
+        let (name, _) = &plan.rejected[0];
+        assert_eq!(name, "#1");
+        assert_eq!(plan.rejection_of(name).map(|conflict| &conflict.kind), Some(&ConflictKind::SameNeedle));
+    }
+}
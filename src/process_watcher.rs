@@ -0,0 +1,127 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::os::fd::{FromRawFd, OwnedFd};
+use tokio::io::unix::AsyncFd;
+use tokio::io::Interest;
+use tokio::sync::mpsc;
+use tokio::time::{sleep, Duration};
+
+// Finds processes by scanning /proc for their names, only reading /proc/<pid>/comm of each.
+// A watched process that started is waited on through a pidfd, so its exit is seen right away
+// without polling. Kernels without pidfd_open get it on the next scan. While every watched
+// process that runs has a pidfd the scans stop, they start again once those exit.
+
+const SCAN_INTERVAL: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone)]
+pub struct Process {
+    pub pid: u32,
+    /// Executable name, truncated to 15 bytes by the kernel
+    pub name: String,
+}
+
+impl Process {
+    /// Arguments of the process, separated by NUL.
+    pub fn cmdline(&self) -> Option<Vec<u8>> {
+        fs::read(format!("/proc/{}/cmdline", self.pid)).ok()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum ProcessEvent {
+    Started(Process),
+    Exited(Process),
+}
+
+/// Every running process.
+pub fn processes() -> impl Iterator<Item = Process> {
+    fs::read_dir("/proc").into_iter().flatten().flatten().filter_map(|entry| {
+        let pid = entry.file_name().to_str()?.parse::<u32>().ok()?;
+        let name = fs::read_to_string(entry.path().join("comm")).ok()?;
+        Some(Process { pid, name: name.trim_end().to_string() })
+    })
+}
+
+/// First process named `name`.
+pub fn find(name: &str) -> Option<Process> {
+    processes().find(|process| process.name == name)
+}
+
+/// Reports the processes `matches` accepts as they start and exit, the ones already running
+/// are reported as started first. Another match starting while the ones seen still run is
+/// only reported once they exited, unless pidfds aren't available.
+pub struct ProcessWatcher {
+    events: mpsc::Receiver<ProcessEvent>,
+}
+
+impl ProcessWatcher {
+    pub fn new(matches: impl Fn(&Process) -> bool + Send + 'static) -> ProcessWatcher {
+        let (sender, events) = mpsc::channel(16);
+        tokio::spawn(run(matches, sender));
+        ProcessWatcher { events }
+    }
+
+    pub async fn next(&mut self) -> Option<ProcessEvent> {
+        self.events.recv().await
+    }
+}
+
+async fn run(matches: impl Fn(&Process) -> bool, events: mpsc::Sender<ProcessEvent>) {
+    // Watched processes, with whether a pidfd reports their exit
+    let mut known: HashMap<u32, (Process, bool)> = HashMap::new();
+    let (exited, mut exits) = mpsc::unbounded_channel::<u32>();
+    loop {
+        let started: Vec<Process> = processes()
+            .filter(|process| !known.contains_key(&process.pid) && matches(process))
+            .collect();
+        for process in started {
+            let pid = process.pid;
+            let waited = match wait_exit(pid) {
+                Ok(wait) => {
+                    let exited = exited.clone();
+                    tokio::spawn(async move {
+                        let _ = wait.readable().await;
+                        let _ = exited.send(pid);
+                    });
+                    true
+                }
+                Err(_) => false,
+            };
+            known.insert(pid, (process.clone(), waited));
+            if events.send(ProcessEvent::Started(process)).await.is_err() {
+                return;
+            }
+        }
+
+        let mut gone: Vec<u32> = known
+            .iter()
+            .filter(|(pid, (_, waited))| !waited && fs::metadata(format!("/proc/{}", pid)).is_err())
+            .map(|(pid, _)| *pid)
+            .collect();
+        // Nothing to scan for while pidfds report the exit of every process seen
+        let scan = known.is_empty() || known.values().any(|(_, waited)| !waited);
+        tokio::select! {
+            Some(pid) = exits.recv() => gone.push(pid),
+            _ = sleep(SCAN_INTERVAL), if scan => {}
+        }
+        for pid in gone {
+            if let Some((process, _)) = known.remove(&pid) {
+                if events.send(ProcessEvent::Exited(process)).await.is_err() {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+// pidfd of `pid`, it becomes readable when the process exits
+fn wait_exit(pid: u32) -> io::Result<AsyncFd<OwnedFd>> {
+    let fd = unsafe { libc::syscall(libc::SYS_pidfd_open, pid as libc::pid_t, 0) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // The syscall returned a new descriptor we now own
+    let fd = unsafe { OwnedFd::from_raw_fd(fd as i32) };
+    AsyncFd::with_interest(fd, Interest::READABLE)
+}
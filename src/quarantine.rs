@@ -0,0 +1,69 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::manifest::fingerprint;
+use crate::patch::Patch;
+use crate::utils::get_steam_patch_dir;
+
+// Patch sets that kept the Steam UI from coming up, kept in ~/steam-patch/quarantine.json
+// so they aren't applied again. They are told apart by the selected and rendered patches, a
+// config change only gives a new patch set when it picks other patches or fills in other
+// values. Removing the file retries the quarantined ones.
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct Quarantine {
+    failures: Vec<Failure>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Failure {
+    /// Fingerprint of the patch set, see `manifest::fingerprint`
+    pub patch_set: String,
+    pub reason: String,
+    pub steam_patch_version: String,
+    /// Unix time of the failure
+    pub time: u64,
+}
+
+fn get_quarantine_path() -> PathBuf {
+    get_steam_patch_dir().join("quarantine.json")
+}
+
+fn read() -> Quarantine {
+    fs::read_to_string(get_quarantine_path())
+        .ok()
+        .and_then(|content| serde_json::from_str(&content).ok())
+        .unwrap_or_default()
+}
+
+/// Failure recorded for `patches`, if they were quarantined.
+pub fn find(patches: &[Patch]) -> Option<Failure> {
+    let patch_set = fingerprint(patches);
+    read().failures.into_iter().find(|failure| failure.patch_set == patch_set)
+}
+
+/// Records that the patch set with the `patch_set` fingerprint broke the Steam UI.
+pub fn add(patch_set: &str, reason: &str) {
+    let mut quarantine = read();
+    quarantine.failures.retain(|failure| failure.patch_set != patch_set);
+    quarantine.failures.push(Failure {
+        patch_set: patch_set.to_string(),
+        reason: reason.to_string(),
+        steam_patch_version: env!("CARGO_PKG_VERSION").to_string(),
+        time: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_secs())
+            .unwrap_or_default(),
+    });
+
+    let path = get_quarantine_path();
+    match serde_json::to_string_pretty(&quarantine) {
+        Ok(content) => match fs::write(&path, content) {
+            Ok(_) => println!("Quarantined patch set {}, remove {:?} to retry it", patch_set, path),
+            Err(e) => eprintln!("Failed to write {:?}: {}", path, e),
+        },
+        Err(e) => eprintln!("Failed to serialize quarantine: {}", e),
+    }
+}
@@ -1,11 +1,28 @@
 use hyper::http::HeaderValue;
+use hyper::server::accept;
 use hyper::service::{make_service_fn, service_fn};
 use hyper::Request;
-use hyper::{body, Body, Method, Response, Server};
-use serde::Deserialize;
+use hyper::{body, Body, Method, Response, Server, StatusCode};
+use serde::{Deserialize, Serialize};
+use lazy_static::lazy_static;
 use std::convert::Infallible;
+use std::fs::{self, OpenOptions};
+use std::io::{Read, Write};
+use std::os::unix::fs::{MetadataExt, OpenOptionsExt, PermissionsExt};
+use std::path::PathBuf;
+use tokio::net::UnixListener;
 
-use crate::devices::create_device;
+use crate::config::get_global_config;
+use crate::devices::{applied_settings, create_device, get_device_name, mapper_state};
+use crate::game;
+use crate::lifecycle;
+use crate::manifest::PatchManifest;
+use crate::quarantine;
+use crate::steam::SteamClient;
+use crate::steam_version;
+use crate::utils::{get_steam_dir, get_steam_patch_dir, has_arg};
+
+const TOKEN_HEADER: &str = "X-Steam-Patch-Token";
 
 #[derive(Deserialize)]
 pub struct SettingsRequest {
@@ -28,7 +45,7 @@ async fn update_settings(req: Request<Body>) -> Result<Response<Body>, Infallibl
         Ok(b) => b,
         Err(e) => {
             println!("Error converting request body to bytes: {}", e);
-            return Ok(Response::new(Body::from("Internal server error")));
+            return Ok(error_response(StatusCode::BAD_REQUEST, "Failed to read request body"));
         },
     };
 
@@ -37,7 +54,7 @@ async fn update_settings(req: Request<Body>) -> Result<Response<Body>, Infallibl
         Ok(req) => req,
         Err(e) => {
             println!("Error deserializing request body: {}", e);
-            return Ok(Response::new(Body::from("Failed to deserialize request body")));
+            return Ok(error_response(StatusCode::BAD_REQUEST, &format!("Failed to deserialize request body: {}", e)));
         },
     };
 
@@ -46,52 +63,239 @@ async fn update_settings(req: Request<Body>) -> Result<Response<Body>, Infallibl
         device.update_settings(settings_request);
     } else {
         println!("Failed to create device.");
+        return Ok(error_response(StatusCode::SERVICE_UNAVAILABLE, "Failed to detect the device"));
     }
 
     println!("Settings updated successfully.");
     Ok(Response::new(Body::from("Settings updated")))
 }
 
+// Tabs of the Steam UI that patches and mapper actions can target
+async fn list_tabs() -> Result<Response<Body>, Infallible> {
+    match SteamClient::list_tabs().await {
+        Ok(tabs) => Ok(json_response(StatusCode::OK, &tabs)),
+        Err(e) => {
+            println!("Error listing Steam tabs: {}", e);
+            Ok(error_response(StatusCode::SERVICE_UNAVAILABLE, "Steam debugger not available"))
+        }
+    }
+}
+
+// What the daemon, Steam and the patches are up to
+fn status() -> Response<Body> {
+    let body = serde_json::json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "device": get_device_name(),
+        "steam": {
+            "state": lifecycle::current(),
+            "client": steam_version::detect(),
+            "game": game::current(),
+        },
+        "patches": lifecycle::patch_status(patch_status),
+        "mapper": mapper_state(),
+    });
+    json_response(StatusCode::OK, &body)
+}
+
+// Patches part of the status, the lifecycle keeps it until Steam changes state
+fn patch_status() -> serde_json::Value {
+    let manifest = PatchManifest::load();
+    // Device and patches are looked up again, they follow the config and patch sets on disk
+    let patches = create_device().map(|device| device.get_patches());
+    serde_json::json!({
+        "patch_set": manifest.as_ref().map(|manifest| &manifest.patch_set),
+        "up_to_date": patches.as_ref().zip(manifest.as_ref())
+            .is_some_and(|(patches, manifest)| manifest.matches(patches, &get_global_config())),
+        "quarantined": patches.as_ref().and_then(|patches| quarantine::find(patches)),
+        "records": manifest.as_ref().map(|manifest| {
+            manifest.patches.iter()
+                .map(|record| serde_json::json!({ "id": record.id, "status": record.status }))
+                .collect::<Vec<_>>()
+        }),
+    })
+}
+
+fn json_response<T: Serialize>(status: StatusCode, body: &T) -> Response<Body> {
+    let mut response = match serde_json::to_string(body) {
+        Ok(body) => Response::new(Body::from(body)),
+        Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, &format!("Failed to serialize response: {}", e)),
+    };
+    *response.status_mut() = status;
+    response.headers_mut().insert("Content-Type", HeaderValue::from_static("application/json"));
+    response
+}
+
+// Failures come back as `{"error": "..."}` with a matching status code
+fn error_response(status: StatusCode, message: &str) -> Response<Body> {
+    json_response(status, &serde_json::json!({ "error": message }))
+}
+
+// Where a request came in, the socket is protected by its file permissions instead
+#[derive(Clone, Copy, PartialEq)]
+enum Transport {
+    Tcp,
+    Socket,
+}
+
+lazy_static! {
+    static ref API_TOKEN: String = load_api_token();
+}
+
+// Stands in for the token in `steam-patch check`
+const CHECK_TOKEN: &str = "00000000000000000000000000000000";
+
+fn get_api_token_path() -> PathBuf {
+    get_steam_patch_dir().join("api_token")
+}
+
+// Reads the token of the HTTP API, creating it on first use. Only root can read it, the
+// patched Steam UI gets it through the {api_token} placeholder.
+fn load_api_token() -> String {
+    let path = get_api_token_path();
+    if let Ok(token) = fs::read_to_string(&path) {
+        return token.trim().to_string();
+    }
+    let mut bytes = [0u8; 16];
+    let created = fs::File::open("/dev/urandom")
+        .and_then(|mut random| random.read_exact(&mut bytes))
+        .map(|_| bytes.iter().map(|byte| format!("{:02x}", byte)).collect::<String>())
+        .and_then(|token| {
+            fs::create_dir_all(get_steam_patch_dir())?;
+            let mut file = OpenOptions::new().write(true).create_new(true).mode(0o600).open(&path)?;
+            file.write_all(token.as_bytes())?;
+            Ok(token)
+        });
+    match created {
+        Ok(token) => token,
+        Err(e) => {
+            // No request can match an empty token
+            eprintln!("Failed to create API token {:?}: {}", path, e);
+            String::new()
+        }
+    }
+}
 
-fn set_cors_headers(mut response: Response<Body>) -> Response<Body> {
+/// Token the patched Steam UI sends with its requests, empty unless `require_token` is set.
+pub fn api_token() -> String {
+    if !get_global_config().require_token {
+        String::new()
+    } else if has_arg("check") {
+        // The dry run only renders the patches, it never creates the token file
+        CHECK_TOKEN.to_string()
+    } else {
+        API_TOKEN.clone()
+    }
+}
+
+// Compares without bailing out at the first difference
+fn token_matches(given: &[u8], token: &[u8]) -> bool {
+    !token.is_empty()
+        && given.len() == token.len()
+        && given.iter().zip(token).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+// Turns away requests that web pages could have sent. Returns the origin to allow in the
+// CORS headers.
+fn check_access(req: &Request<Body>) -> Result<Option<HeaderValue>, (StatusCode, String)> {
+    let config = get_global_config();
+    let headers = req.headers();
+
+    // Pages on a rebound domain send their own host name
+    let host = headers.get("Host").and_then(|host| host.to_str().ok()).unwrap_or_default();
+    let host_name = host.rsplit_once(':').map_or(host, |(name, _)| name);
+    if !matches!(host_name, "localhost" | "127.0.0.1") {
+        return Err((StatusCode::FORBIDDEN, format!("Host {} not allowed", host)));
+    }
+
+    // Browsers always say where a request comes from, local tools don't have to
+    let origin = headers.get("Origin").cloned();
+    if let Some(origin) = &origin {
+        let allowed = origin
+            .to_str()
+            .is_ok_and(|origin| config.allowed_origins.iter().any(|allowed| allowed == origin));
+        if !allowed {
+            return Err((StatusCode::FORBIDDEN, format!("Origin {:?} not allowed", origin)));
+        }
+    }
+
+    // Preflights never carry the token
+    if config.require_token && req.method() != Method::OPTIONS {
+        let given = headers.get(TOKEN_HEADER).map(|token| token.as_bytes()).unwrap_or_default();
+        if !token_matches(given, API_TOKEN.as_bytes()) {
+            return Err((StatusCode::UNAUTHORIZED, "Missing or wrong API token".to_string()));
+        }
+    }
+    Ok(origin)
+}
+
+fn set_cors_headers(mut response: Response<Body>, origin: Option<HeaderValue>) -> Response<Body> {
     let headers = response.headers_mut();
 
-    headers.insert("Access-Control-Allow-Origin", HeaderValue::from_static("*"));
+    if let Some(origin) = origin {
+        headers.insert("Access-Control-Allow-Origin", origin);
+    }
+    headers.insert("Vary", HeaderValue::from_static("Origin"));
     headers.insert(
         "Access-Control-Allow-Methods",
-        HeaderValue::from_static("GET, POST, PUT, DELETE, OPTIONS"),
+        HeaderValue::from_static("GET, POST, OPTIONS"),
     );
     headers.insert(
         "Access-Control-Allow-Headers",
-        HeaderValue::from_static("*"),
+        HeaderValue::from_static("Content-Type, X-Steam-Patch-Token"),
     );
 
     response
 }
 
-async fn router(req: Request<Body>) -> Result<Response<Body>, Infallible> {
+async fn router(req: Request<Body>, transport: Transport) -> Result<Response<Body>, Infallible> {
+    let origin = if transport == Transport::Tcp {
+        match check_access(&req) {
+            Ok(origin) => origin,
+            Err((status, message)) => {
+                println!("Refused {} {}: {}", req.method(), req.uri().path(), message);
+                return Ok(set_cors_headers(error_response(status, &message), None));
+            }
+        }
+    } else {
+        None
+    };
+
     let path = req.uri().path(); // Get the path of the request
     println!("Routing request to {}", path);
 
     let response = match (req.method(), path) {
+        // CORS preflight of the requests Steam sends
+        (&Method::OPTIONS, _) => {
+            let mut response = Response::new(Body::empty());
+            *response.status_mut() = StatusCode::NO_CONTENT;
+            Ok(response)
+        }
         (&Method::POST, "/update_settings") => {
             println!("Handling POST request to /update_settings");
             update_settings(req).await
         },
+        (&Method::GET, "/tabs") => list_tabs().await,
+        (&Method::GET, "/game") => Ok(json_response(StatusCode::OK, &serde_json::json!({ "app_id": game::current() }))),
+        (&Method::GET, "/status") => Ok(status()),
+        (&Method::GET, "/settings") => Ok(json_response(StatusCode::OK, &applied_settings())),
+        (_, "/update_settings" | "/tabs" | "/game" | "/status" | "/settings") => {
+            println!("Method {} not allowed on {}", req.method(), path);
+            Ok(error_response(StatusCode::METHOD_NOT_ALLOWED, &format!("{} is not allowed on {}", req.method(), path)))
+        },
         _ => {
             println!("No route found for {} {}", req.method(), path);
-            Ok(Response::new(Body::from("404 Not Found")))
+            Ok(error_response(StatusCode::NOT_FOUND, &format!("No route for {}", path)))
         },
     };
 
     println!("Request routed, setting CORS headers.");
-    Ok(set_cors_headers(response?))
+    Ok(set_cors_headers(response?, origin))
 }
 
 pub async fn run() {
     let make_svc = make_service_fn(|_conn| async {
         println!("Connection established, creating service.");
-        Ok::<_, Infallible>(service_fn(router))
+        Ok::<_, Infallible>(service_fn(|req| router(req, Transport::Tcp)))
     });
 
     let addr = ([127, 0, 0, 1], 1338).into();
@@ -101,7 +305,39 @@ pub async fn run() {
 
     println!("Server is running on http://{}", addr);
 
-    if let Err(e) = server.await {
+    let socket_path = get_global_config().api_socket;
+    if socket_path.is_empty() {
+        if let Err(e) = server.await {
+            println!("Server error: {}", e);
+        }
+        return;
+    }
+    let (tcp, socket) = tokio::join!(server, serve_socket(PathBuf::from(socket_path)));
+    if let Err(e) = tcp {
         println!("Server error: {}", e);
     }
+    if let Err(e) = socket {
+        println!("Socket server error: {}", e);
+    }
+}
+
+// Same API on a Unix socket for local tools, only root and the Steam user can connect
+async fn serve_socket(path: PathBuf) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    // Left over by an earlier run
+    let _ = fs::remove_file(&path);
+    let listener = UnixListener::bind(&path)?;
+    let owner = fs::metadata(get_steam_dir())?;
+    std::os::unix::fs::chown(&path, Some(owner.uid()), Some(owner.gid()))?;
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o600))?;
+    println!("Server is running on {:?}", path);
+
+    let connections = futures::stream::unfold(listener, |listener| async {
+        let connection = listener.accept().await.map(|(stream, _)| stream);
+        Some((connection, listener))
+    });
+    let make_svc = make_service_fn(|_conn| async {
+        Ok::<_, Infallible>(service_fn(|req| router(req, Transport::Socket)))
+    });
+    Server::builder(accept::from_stream(connections)).serve(make_svc).await?;
+    Ok(())
 }
@@ -1,149 +1,187 @@
 #![allow(non_snake_case)] // Allow non-snake_case identifiers
 
+use crate::backup::{self, Restore};
+use crate::cdp::{CdpClient, CdpError, Event, TabInfo, TabSelector};
 use crate::devices::create_device;
-use crate::patch::Patch;
-use crate::utils::get_username;
-use dirs::home_dir;
-use hyper::{Client, Uri, body};
-use inotify::{Inotify, WatchMask};
-use serde::{Deserialize};
-use tungstenite::http::response;
-use std::collections::HashMap;
-use std::env;
-use std::f32::consts::E;
-use std::fs::{self, File};
-use std::io::{BufRead, BufReader, Error};
-use std::os::unix::process;
+use crate::engine::{self, Outcome};
+use crate::lifecycle::{self, SteamState};
+use crate::config::get_global_config;
+use crate::console;
+use crate::manifest::{PatchManifest, PatchRecord, PatchStatus};
+use crate::patch::{AppliedPatch, Patch, PatchKind};
+use crate::planner;
+use crate::process_watcher::{self, ProcessEvent, ProcessWatcher};
+use crate::quarantine;
+use crate::update_watcher;
+use crate::utils::{get_remote_debugging_flag, get_steam_dir, write_file_atomic};
+use serde::de::DeserializeOwned;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::Error;
+use std::os::unix::fs::MetadataExt;
 use std::path::PathBuf;
-use sysinfo::{ProcessExt, SystemExt};
-use tokio::time::{sleep, Duration, Instant};
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tungstenite::connect;
-use tungstenite::stream::MaybeTlsStream;
-use tungstenite::{Message, WebSocket};
+use std::sync::Arc;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::time::{Duration, Instant};
 use std::option::Option;
-use std::process::{Command, Stdio};
-use std::thread;
-use reqwest;
 use serde_json::Value;
 
 
-#[derive(Deserialize)]
-struct Tab {
-    title: String,
-    webSocketDebuggerUrl: String,
-}
+// How long the Steam UI has to stay up after patching to count as started
+const UI_SETTLE_TIME: Duration = Duration::from_secs(10);
+// How often the debugger is asked whether the Steam UI is up
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+// How long Steam can run without its debugger answering before it is reported
+const DEBUGGER_GRACE_TIME: Duration = Duration::from_secs(30);
 
 pub struct SteamClient {
-    socket: Option<WebSocket<MaybeTlsStream<std::net::TcpStream>>>,
+    // One debugger session per targeted tab
+    sessions: HashMap<TabSelector, CdpClient>,
+    // Whether the sessions forward the console of their tab into the log, only the client
+    // of `watch` does so every line is logged once
+    console: bool,
 }
 
 impl SteamClient {
-    pub fn patch(&mut self, patches: Vec<Patch>) -> Result<(), Error> {
-        let mut opened_files: HashMap<String, String> = HashMap::new();
-
-        for patch in &patches {
-            // Use `match` or `if let` to handle the Option returned by `get_file()`
-            // println!("Applying patch: Curr->{:?}", patch.text_to_find);
-            if let Ok(Some(path_file)) = patch.destination.get_file() {
-                // Convert the `Path` to a string and handle the potential `None` case
-                if let Some(path_str) = path_file.to_str() {
-                    let path_string = path_str.to_string();
-                    // Handle the Result of `read_to_string` with `unwrap_or_else`
-                    let content = opened_files
-                        .entry(path_string.clone())
-                        .or_insert_with(|| {
-                            match fs::read_to_string(&path_file) {
-                                Ok(content) => {
-                                    // println!("File read successfully: {}", path_string);
-                                    content
-                                }
-                                Err(e) => {
-                                    // Handle the error, e.g., by logging and continuing with an empty string
-                                    eprintln!("Error reading the file '{}': {}", path_string, e);
-                                    String::new() // return an empty string on error
-                                }
-                            }
-                        });
-                    let text_to_find = &patch.text_to_find;
-                    let replacement_text = &patch.replacement_text;
-                    if content.contains(text_to_find) {
-                        // println!("Success: New->{}", replacement_text);
-                        *content = content.replace(text_to_find, replacement_text);
-                    } else {
-                        println!("Failed to patch: '{}'", replacement_text);
-                    }
-                } else {
-                    // Handle the error if path_str is None
-                    eprintln!("Failed to convert the path to a string for file: {:?}", path_file);
+    pub fn patch(&mut self, patches: Vec<Patch>) -> Result<PatchManifest, Error> {
+        let mut manifest = PatchManifest::new(&patches);
+        let config = get_global_config();
+
+        // Injected patches are run over the debugger by `inject`, they never touch the files
+        let (injected, patches): (Vec<Patch>, Vec<Patch>) = patches.into_iter().partition(Patch::is_injected);
+        for patch in &injected {
+            manifest.patches.push(PatchRecord::new(patch, &config, PatchStatus::Injected));
+        }
+
+        let plan = planner::plan(patches);
+        plan.log_conflicts();
+        for (name, patch) in &plan.rejected {
+            let status = match plan.rejection_of(name) {
+                Some(conflict) if !conflict.is_error() => PatchStatus::Skipped(conflict.to_string()),
+                Some(conflict) => PatchStatus::Failed(conflict.to_string()),
+                None => PatchStatus::Failed("Conflicts with another patch".to_string()),
+            };
+            manifest.patches.push(PatchRecord::new(patch, &config, status));
+        }
+
+        let mut files = match engine::load_files(&plan.patches) {
+            Ok(files) => files,
+            Err(e) => {
+                eprintln!("Failed to patch: {}", e);
+                Vec::new()
+            }
+        };
+        // Hash of every file before and after patching, the records of its patches refer to them
+        let hashes_before: HashMap<PathBuf, String> = files
+            .iter()
+            .map(|file| (file.path.clone(), backup::hash(file.content.as_bytes())))
+            .collect();
+        let results = engine::run(plan.patches, &mut files);
+        let hashes_after: HashMap<PathBuf, String> = files
+            .iter()
+            .filter(|file| file.changed)
+            .map(|file| (file.path.clone(), backup::hash(file.content.as_bytes())))
+            .collect();
+
+        for (patch, outcome) in results {
+            let mut record = PatchRecord::new(&patch, &config, PatchStatus::NotFound);
+            match outcome {
+                Outcome::Applied { file, substitution } => {
+                    record.hash_before = hashes_before.get(&file).cloned();
+                    record.hash_after = hashes_after.get(&file).cloned();
+                    record.file = Some(file);
+                    record.status = PatchStatus::Applied;
+                    record.substitutions = vec![substitution];
+                }
+                Outcome::NotFound => println!("Failed to patch: '{}' not found", patch.id),
+                Outcome::Ambiguous { files, matches } => {
+                    let e = format!("'{}' found {} times in {:?}", patch.id, matches, files);
+                    eprintln!("Failed to patch: {}", e);
+                    record.status = PatchStatus::Failed(e);
+                }
+                Outcome::Failed(e) => {
+                    eprintln!("Failed to patch: {}", e);
+                    record.status = PatchStatus::Failed(e);
                 }
-            } else {
-                // Handle the error if get_file() returns None
-                eprintln!("Failed to get the file from destination or the path is not valid for patch: {:?}", patch);
             }
+            manifest.patches.push(record);
         }
 
         println!("Writing changes to disk...");
-        for (path, content) in &opened_files {
-            match fs::write(path, content) {
-                Ok(_) => println!("File written successfully: {}", path),
-                Err(e) => eprintln!("Failed to write to file '{}': {}", path, e),
+        for file in files.iter().filter(|file| file.changed) {
+            let (path, content) = (&file.path, &file.content);
+            // Never write a file we couldn't keep a pristine copy of
+            let written = backup::backup_before_write(path).and_then(|_| {
+                write_file_atomic(path, content.as_bytes())
+                    .map_err(|e| format!("Failed to write to file '{:?}': {}", path, e))
+            });
+            match written {
+                Ok(_) => {
+                    println!("File written successfully: {:?}", path);
+                    if let Err(e) = backup::record_written(path, content.as_bytes()) {
+                        eprintln!("{}", e);
+                    }
+                    manifest.files.insert(path.clone(), hashes_after[path].clone());
+                }
+                Err(e) => {
+                    eprintln!("{}", e);
+                    for record in manifest.patches.iter_mut() {
+                        if record.file.as_ref() == Some(path) && record.status == PatchStatus::Applied {
+                            record.status = PatchStatus::Failed(e.clone());
+                        }
+                    }
+                }
             };
         }
         println!("Patching complete.");
 
 
 
-        Ok(())
+        Ok(manifest)
     }
 
-    pub fn unpatch(&mut self, patches: Vec<Patch>) -> Result<(), Error> {
-        let mut opened_files: HashMap<String, String> = HashMap::new();
-
-
-        for patch in &patches {
-            if let Ok(Some(path_file)) = patch.destination.get_file() {
-                // Convert the `Path` to a string and handle the potential `None` case
-                if let Some(path_str) = path_file.to_str() {
-                    let path_string = path_str.to_string();
-                    // Handle the Result of `read_to_string` with `unwrap_or_else`
-                    let content = opened_files
-                        .entry(path_string.clone())
-                        .or_insert_with(|| {
-                            match fs::read_to_string(&path_file) {
-                                Ok(content) => {
-                                    content
-                                }
-                                Err(e) => {
-                                    // Handle the error, e.g., by logging and continuing with an empty string
-                                    eprintln!("Error reading the file '{}': {}", path_string, e);
-                                    String::new() // return an empty string on error
-                                }
-                            }
-                        });
-                    let text_to_find = &patch.text_to_find;
-                    let replacement_text = &patch.replacement_text;
-                    if content.contains(replacement_text) {
-                        // println!("Success: New->{}", text_to_find);
-                        *content = content.replace(replacement_text, text_to_find);
-                    } else {
-                        println!("Failed to unpatch: '{}'", replacement_text);
+    // Restores the pristine copy of every patched file, files without a backup get the
+    // recorded substitutions reverted, newest first so chained patches unwind in order
+    pub fn unpatch(&mut self, applied: Vec<AppliedPatch>) -> Result<(), Error> {
+        let mut opened_files: HashMap<PathBuf, String> = HashMap::new();
+        let mut restored: HashMap<PathBuf, bool> = HashMap::new();
+
+
+        for patch in applied.iter().rev() {
+            let skip = *restored.entry(patch.file.clone()).or_insert_with(|| {
+                match backup::restore(&patch.file) {
+                    Ok(Restore::Restored) | Ok(Restore::Changed) => true,
+                    Ok(Restore::NoBackup) => false,
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        false
                     }
-                } else {
-                    // Handle the error if path_str is None
-                    eprintln!("Failed to convert the path to a string for file: {:?}", path_file);
                 }
-            } else {
-                // Handle the error if get_file() returns None
-                eprintln!("Failed to get the file from destination or the path is not valid for patch: {:?}", patch);
+            });
+            if skip {
+                continue;
             }
+
+            let content = match opened_files.entry(patch.file.clone()) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => match fs::read_to_string(&patch.file) {
+                    Ok(content) => entry.insert(content),
+                    Err(e) => {
+                        // Never write back a file we couldn't read
+                        eprintln!("Error reading the file '{:?}', leaving it as is: {}", patch.file, e);
+                        continue;
+                    }
+                },
+            };
+            patch.revert(content);
         }
 
         println!("Reverting changes to disk...");
         for (path, content) in &opened_files {
-            match fs::write(path, content) {
-                Ok(_) => println!("File reverted successfully: {}", path),
-                Err(e) => eprintln!("Failed to revert file '{}': {}", path, e),
+            match write_file_atomic(path, content.as_bytes()) {
+                Ok(_) => println!("File reverted successfully: {:?}", path),
+                Err(e) => eprintln!("Failed to revert file '{:?}': {}", path, e),
             };
         }
         println!("Unpatching complete.");
@@ -151,209 +189,256 @@ impl SteamClient {
         Ok(())
     }
 
-    async fn send_message(&mut self, message: serde_json::Value) {
-        let mut retries = 3;
+    // Debugger session to the tab `tab` selects, connecting in the background on first use
+    fn session(&mut self, tab: &TabSelector) -> &CdpClient {
+        self.sessions.entry(tab.clone()).or_insert_with(|| {
+            let selector = tab.clone();
+            // Page events tell when a reload finished loading
+            let setup: &[&str] = if self.console { &["Page.enable", "Runtime.enable"] } else { &["Page.enable"] };
+            let session = CdpClient::new(
+                Arc::new(move || {
+                    let tab = selector.clone();
+                    Box::pin(async move { Self::find_tab(&tab).await.map(|tab| tab.web_socket_debugger_url) })
+                }),
+                setup,
+            );
+            if self.console {
+                console::forward(tab.to_string(), session.subscribe());
+            }
+            session
+        })
+    }
 
-        while retries > 0 {
-            match self.socket.as_mut() {
-                Some(socket) => match socket.send(Message::Text(message.to_string())) {
-                    Ok(_) => {
-                        println!("Successfully sent message., {:}", message);
-                        break
-                    }
-                    Err(_) => {
-                        eprintln!("Couldn't send message to Steam, retrying...");
-                        self.connect().await;
-                        retries -= 1;
-                    }
-                },
-                None => {
-                    self.connect().await;
-                    retries -= 1;
+    /// Sends a DevTools command to SharedJSContext and waits for its result.
+    pub async fn call(&mut self, method: &str, params: Value) -> Result<Value, CdpError> {
+        self.call_in(&TabSelector::default(), method, params).await
+    }
+
+    pub async fn call_in(&mut self, tab: &TabSelector, method: &str, params: Value) -> Result<Value, CdpError> {
+        self.session(tab).call(method, params).await
+    }
+
+    pub async fn reboot(&mut self) {
+        if let Err(e) = self.call("Page.reload", serde_json::json!({})).await {
+            eprintln!("Couldn't reload Steam: {}", e);
+        }
+    }
+
+    /// Evaluates `expression` in SharedJSContext and deserializes its value, promises are
+    /// awaited. Like `steam.query::<u32>("SteamClient.Apps...")`.
+    pub async fn query<T: DeserializeOwned>(&mut self, expression: &str) -> Result<T, CdpError> {
+        self.query_in(&TabSelector::default(), expression).await
+    }
+
+    pub async fn query_in<T: DeserializeOwned>(&mut self, tab: &TabSelector, expression: &str) -> Result<T, CdpError> {
+        self.session(tab).query(expression).await
+    }
+
+    /// Events of SharedJSContext from now on, like the calls of a `Runtime.addBinding` binding.
+    pub fn subscribe(&mut self) -> broadcast::Receiver<Event> {
+        self.session(&TabSelector::default()).subscribe()
+    }
+
+    /// Reloads SharedJSContext and waits up to `limit` for the new document to finish loading.
+    pub async fn reload(&mut self, limit: Duration) {
+        let mut events = self.subscribe();
+        if let Err(e) = self.call("Page.reload", serde_json::json!({})).await {
+            eprintln!("Couldn't reload Steam: {}", e);
+            return;
+        }
+        let loaded = tokio::time::timeout(limit, async {
+            loop {
+                match events.recv().await {
+                    Ok(event) if event.method == "Page.loadEventFired" => return,
+                    Err(RecvError::Closed) => return,
+                    _ => continue,
                 }
             }
+        });
+        if loaded.await.is_err() {
+            eprintln!("Steam UI didn't finish loading within {}s", limit.as_secs());
         }
     }
 
-    pub async fn reboot(&mut self) {
-        self.send_message(serde_json::json!({
-            "id": 1,
-            "method": "Page.reload",
-        }))
-        .await;
+    /// Runs `js_code` in SharedJSContext, like the mapper actions.
+    pub async fn execute(&mut self, js_code: &str) {
+        self.execute_in(&TabSelector::default(), js_code).await
     }
 
-    pub async fn execute(&mut self, js_code: &str) {
-        self.send_message(serde_json::json!({
-            "id": 1,
-            "method": "Runtime.evaluate",
-            "params": {
-                "expression": js_code,
+    /// Runs `js_code` in the tab `tab` selects.
+    pub async fn execute_in(&mut self, tab: &TabSelector, js_code: &str) {
+        // Don't hold up the caller until the request times out
+        if !lifecycle::current().is_ui_up() {
+            eprintln!("Steam UI isn't up, not running script in tab {}", tab);
+            return;
+        }
+        let params = serde_json::json!({ "expression": console::tag(js_code, "execute") });
+        if let Err(e) = self.session(tab).evaluate(params).await {
+            eprintln!("Couldn't run script in Steam tab {}: {}", tab, e);
+        }
+    }
+
+    /// Registers the new document scripts of injected patches, they run before Steam's own
+    /// code on every reload for as long as this debugger session lives.
+    pub async fn register_scripts(&mut self, patches: &[Patch]) {
+        for patch in patches.iter().filter(|patch| patch.kind == PatchKind::NewDocument) {
+            println!("Registering script of patch: {}", patch.id);
+            let params = serde_json::json!({ "source": console::tag(&patch.script, &patch.id) });
+            if let Err(e) = self.call_in(&patch.tab, "Page.addScriptToEvaluateOnNewDocument", params).await {
+                eprintln!("Couldn't register script of patch '{}': {}", patch.id, e);
             }
-        }))
-        .await;
+        }
     }
 
-    async fn get_context() -> Option<String> {
-        println!("Getting Steam...");
+    /// Runs the script of every injected patch in the current document.
+    pub async fn inject(&mut self, patches: &[Patch]) {
+        for patch in patches.iter().filter(|patch| patch.is_injected()) {
+            println!("Injecting patch: {}", patch.id);
+            let params = serde_json::json!({ "expression": console::tag(&patch.script, &patch.id) });
+            if let Err(e) = self.session(&patch.tab).evaluate(params).await {
+                eprintln!("Injected patch '{}' failed: {}", patch.id, e);
+            }
+        }
+    }
 
-        let client = Client::new();
-        let start_time = Instant::now();
-        let uri = match "http://localhost:8080/json".parse::<Uri>() {
-            Ok(uri) => uri,
-            Err(_) => {
-                println!("Error: Invalid URI.");
-                return None;
+    /// Every tab of the Steam UI the debugger lists.
+    pub async fn list_tabs() -> Result<Vec<TabInfo>, reqwest::Error> {
+        reqwest::get(Self::debug_url()).await?.error_for_status()?.json().await
+    }
+
+    // First tab `tab` selects that is free to debug, `None` while it isn't open
+    async fn find_tab(tab: &TabSelector) -> Option<TabInfo> {
+        Self::list_tabs()
+            .await
+            .ok()?
+            .into_iter()
+            .find(|info| !info.web_socket_debugger_url.is_empty() && tab.matches(info))
+    }
+
+    pub fn new() -> SteamClient {
+        SteamClient { sessions: HashMap::new(), console: false }
+    }
+
+    /// A client whose sessions also log the console of their tab, for the one that watches Steam.
+    pub fn with_console() -> SteamClient {
+        SteamClient { sessions: HashMap::new(), console: true }
+    }
+
+    pub async fn connect(&mut self) {
+        self.session(&TabSelector::default());
+    }
+
+    // Unpatches the previous run when it doesn't match the current patches, then patches.
+    // Returns whether Steam was patched.
+    fn repatch(&mut self, patches: Vec<Patch>) -> bool {
+        if Self::is_quarantined(&patches) {
+            self.unpatch_manifest();
+            return false;
+        }
+        let config = get_global_config();
+        if let Some(manifest) = PatchManifest::load() {
+            if manifest.matches(&patches, &config) {
+                println!("Steam is already patched with the current patches.");
+                return false;
             }
-        };
+            self.unpatch_manifest();
+        }
 
-        println!("URI: {}", uri);
-        //Retry time
-        while start_time.elapsed() < Duration::from_secs(60) {
-            match client.get(uri.clone()).await {
-                Ok(response) => {
-                    match body::to_bytes(response.into_body()).await {
-                        Ok(bytes) => {
-                            match serde_json::from_slice::<Vec<Tab>>(&bytes) {
-                                Ok(tabs) => {
-                                    if let Some(tab) = tabs.into_iter().find(|tab| {
-                                        tab.title == "SharedJSContext" && !tab.webSocketDebuggerUrl.is_empty()
-                                    }) {
-                                        return Some(tab.webSocketDebuggerUrl);
-                                    } 
-                                }
-                                Err(_) => println!("Error: Failed to deserialize the response."),
-                            }
-                        }
-                        Err(_) => println!("Error: Failed to read the response body."),
-                    }
-                }
-                Err(e) => {
-                    println!("Couldn't connect to Steam, retrying... {:?}",e);
-                    if e.is_connect(){
-                        println!("Failed to connect to host.");
-                    } else if e.is_timeout(){
-                        println!("Connection has timed out.");
-                    } else {
-                        println!("Error: {:?}", e);
-                    }
+        match self.patch(patches) {
+            Ok(manifest) => {
+                println!("Steam patched");
+                if let Err(e) = manifest.save() {
+                    eprintln!("Couldn't write patch manifest: {}", e);
                 }
+                true
+            },
+            Err(_) => {
+                eprintln!("Couldn't patch Steam");
+                false
             }
-            sleep(Duration::from_millis(50)).await;
         }
-        println!("Timeout while trying to fetch Steam data!");
-        None
-        
     }
 
-    pub fn new() -> SteamClient {
-        return SteamClient { socket: None };
+    // Reverts the patches of the last run and forgets them, returns the manifest they came from
+    fn unpatch_manifest(&mut self) -> Option<PatchManifest> {
+        let manifest = PatchManifest::load()?;
+        match self.unpatch(manifest.applied()) {
+            Ok(_) => {
+                println!("Unpatching to remove previous patches and repatching.");
+                let _ = PatchManifest::remove();
+            },
+            Err(_) => eprintln!("Couldn't unpatch Steam"),
+        }
+        Some(manifest)
     }
 
-    pub async fn connect(&mut self) {
-        if let Some(context) = Self::get_context().await {
-            self.socket = match connect(context) {
-                Ok((socket, _)) => Some(socket),
-                Err(_) => None,
-            };
+    fn is_quarantined(patches: &[Patch]) -> bool {
+        match quarantine::find(patches) {
+            Some(failure) => {
+                eprintln!("Not patching Steam, these patches broke the Steam UI before: {}", failure.reason);
+                true
+            }
+            None => false,
         }
     }
-    fn is_patched() -> bool {
-        let username = get_username(); // Assuming get_username() correctly returns a String
-    
-        // Use PathBuf for building the file path
-        let mut path = if let Some(home_dir) = dirs::home_dir() {
-            home_dir
-        } else {
-            return false;
-        };
-        path.push(format!("/home/{}/steam-patch/patched", username));
-        path.exists()
+
+    // Puts the pristine files back after the patched UI failed to come up, the patch set
+    // is quarantined so it isn't applied again
+    async fn rollback(&mut self, reason: &str) {
+        eprintln!("Rolling back Steam patches: {}", reason);
+        if let Some(manifest) = self.unpatch_manifest() {
+            quarantine::add(&manifest.patch_set, reason);
+        }
+        // Only gets through if SharedJSContext is still up
+        self.reboot().await;
     }
-    fn create_patched_file() -> Result<(), Error> {
-        let username = get_username(); 
-    
-        // Use PathBuf for building the file path
-        let mut path = if let Some(home_dir) = dirs::home_dir() {
-            home_dir
-        } else {
-            // Return an error if home directory cannot be determined
-            return Err(Error::new(std::io::ErrorKind::NotFound, "Home directory not found"));
-        };
-    
-        // Append the remaining path components
-        path.push(format!("/home/{}/steam-patch/patched", username));
-    
-        // Create the file
-        println!("Creating file at {:?}", path);
-        File::create(path)?;
-    
-        Ok(())
+
+    // True when there is no manifest for the current patches yet
+    fn needs_patch(patches: &[Patch]) -> bool {
+        match PatchManifest::load() {
+            Some(manifest) => !manifest.matches(patches, &get_global_config()),
+            None => true,
+        }
     }
-    fn remove_patched_file() -> Result<(), Error> {
-        let username = get_username();
-    
-        // Use PathBuf for building the file path
-        let mut path = if let Some(home_dir) = dirs::home_dir() {
-            home_dir
-        } else {
-            // Return an error if home directory cannot be determined
-            return Err(Error::new(std::io::ErrorKind::NotFound, "Home directory not found"));
-        };
-    
-        // Append the remaining path components
-        path.push(format!("/home/{}/steam-patch/patched", username));
-    
-        // Remove the file
-        println!("Removing file at {:?}", path);
-        fs::remove_file(path)?;
-    
-        Ok(())
+
+    // Tab list of the CEF debugger, where Steam's remote debugging listens
+    fn debug_url() -> String {
+        let config = get_global_config();
+        format!("http://{}:{}/json", config.cef_host, config.cef_port)
     }
-    
-    const DEBUG_URL: &str = "http://localhost:8080/json";
-    const TABS_TO_FIND: &'static[&'static str] = &["SharedJSContext"];
 
-    async fn find_tabs() -> Result<bool, reqwest::Error> {
-        let response = reqwest::get(Self::DEBUG_URL).await?;
-
-        if response.status().is_success() {
-            let tabs: Vec<Value> = response.json().await?;
-            for tab in tabs {
-                if let Some(title) = tab["title"].as_str() {
-                    if Self::TABS_TO_FIND.contains(&title) {
-                        return Ok(true);
-                    }
-                }
-            }
+    /// Creates the flag file that makes Steam start with remote debugging on, as the user owning
+    /// the Steam directory. Returns whether it was missing, Steam only reads it when starting.
+    fn enable_remote_debugging() -> Result<bool, Error> {
+        let flag = get_remote_debugging_flag();
+        if flag.exists() {
+            return Ok(false);
         }
-        Ok(false)
+        let owner = fs::metadata(get_steam_dir())?;
+        fs::File::create(&flag)?;
+        std::os::unix::fs::chown(&flag, Some(owner.uid()), Some(owner.gid()))?;
+        println!("Enabled Steam remote debugging with {:?}", flag);
+        Ok(true)
+    }
+
+    // Whether the tab the UI is driven from is up
+    async fn find_tabs() -> Result<bool, reqwest::Error> {
+        let tabs = Self::list_tabs().await?;
+        Ok(tabs.iter().any(|tab| TabSelector::default().matches(tab)))
     }
 
     pub async fn watch() -> Option<tokio::task::JoinHandle<()>> {
+        if let Err(e) = Self::enable_remote_debugging() {
+            eprintln!("Failed to enable Steam remote debugging with {:?}: {}", get_remote_debugging_flag(), e);
+        }
+
         // If Steam client is already running, patch it and restart
-        let mut client = Self::new();
-        if Self::is_running()  {
+        let mut client = Self::with_console();
+        if process_watcher::find("steam").is_some() {
             client.connect().await;
             if let Some(device) = create_device() {
-                //Attempt to unpatch previous changes
-                if Self::is_patched(){
-                    match client.unpatch(device.get_patches()) {
-                        Ok(_) => {
-                            println!("Unpatching to remove previous patches and repatching.");
-                            let _ = Self::remove_patched_file();
-                        },
-                        Err(_) => eprintln!("Couldn't unpatch Steam"),
-                    }
-                }
-                //Then repatches new changes
-                match client.patch(device.get_patches()) {
-                    Ok(_) => {
-                        println!("Steam was running and patched");
-                        let _ = Self::create_patched_file();
-                    },
-                    Err(_) => eprintln!("Couldn't patch Steam"),
-                }
+                //Unpatches previous changes if they are outdated, then repatches new changes
+                let _ = client.repatch(device.get_patches());
             }
             // println!("Rebooting client");
             // client.reboot().await;
@@ -362,74 +447,134 @@ impl SteamClient {
 
         println!("Watching Steam cef status...");
         let task = tokio::spawn(async move {
-            let mut patched = false;
-            let mut server_was_down = false;
-
+            let mut updates = update_watcher::spawn();
+            let mut steam_processes = ProcessWatcher::new(|process| process.name == "steam");
+            // Pids of the running Steam processes
+            let mut steam_pids: HashSet<u32> = HashSet::new();
+            // When the UI was reloaded with new patches, while Reloading
+            let mut reloaded_at = Instant::now();
+            // Steam restarts right after patching, reset once the patched UI settles
+            let mut restarts = 0;
+            // Since when the debugger hasn't answered, and whether that was reported
+            let mut down_since: Option<Instant> = None;
+            let mut reported_no_debugger = false;
 
             loop {
+                let state = lifecycle::current();
                 match SteamClient::find_tabs().await {
-                    Ok(tabs_found) => {
-                        server_was_down = false;
-                        if tabs_found {
-                            if !SteamClient::is_patched() {
-                                if let Some(device) = create_device() {
-                                    match client.patch(device.get_patches()) {
-                                        Ok(_) => {
-                                            println!("Steam patched");
-                                            let _ = Self::create_patched_file();
-                                        },
-                                        Err(_) => eprintln!("Couldn't patch Steam"),
+                    Err(_) => {
+                        if state != SteamState::SteamNotRunning {
+                            let manifest = client.unpatch_manifest();
+                            // Steam went down before the patched UI settled
+                            if state == SteamState::Reloading {
+                                restarts += 1;
+                                if restarts >= get_global_config().max_steam_restarts {
+                                    restarts = 0;
+                                    let reason = format!("Steam restarted {} times right after patching", get_global_config().max_steam_restarts);
+                                    eprintln!("Rolling back Steam patches: {}", reason);
+                                    if let Some(manifest) = manifest {
+                                        quarantine::add(&manifest.patch_set, &reason);
                                     }
-                                    
                                 }
-                                println!("Rebooting client");
-                                client.reboot().await;
-                                patched = true;
-                                println!(r#"{{"status": "patched"}}"#);
+                            }
+                            lifecycle::set(SteamState::SteamNotRunning);
+                        }
+
+                        let down = *down_since.get_or_insert_with(Instant::now);
+                        if !reported_no_debugger && down.elapsed() >= DEBUGGER_GRACE_TIME && !steam_pids.is_empty() {
+                            reported_no_debugger = true;
+                            let created = Self::enable_remote_debugging().unwrap_or(false);
+                            eprintln!(
+                                "Steam is running but its debugger doesn't answer at {}, Steam can't be patched.",
+                                Self::debug_url()
+                            );
+                            if created {
+                                eprintln!("Remote debugging was off, it will be on once Steam restarts.");
+                            } else {
+                                eprintln!(
+                                    "Check that {:?} exists and that cef_host and cef_port match Steam's debugger.",
+                                    get_remote_debugging_flag()
+                                );
                             }
                         }
                     }
-                    Err(_) => {
-                        if !server_was_down {
-                            server_was_down = true;
-                            if SteamClient::is_patched() {
-                                if let Some(device) = create_device() {
-                                    match client.unpatch(device.get_patches()) {
-                                        Ok(_) => {
-                                            println!("Unpatching to remove previous patches and repatching.");
-                                            let _ = Self::remove_patched_file();
-                                        },
-                                        Err(_) => eprintln!("Couldn't unpatch Steam"),
+                    Ok(false) => {
+                        down_since = None;
+                        reported_no_debugger = false;
+                        let timeout = Duration::from_secs(get_global_config().rollback_timeout);
+                        if state != SteamState::Reloading {
+                            lifecycle::set(SteamState::CefUp);
+                        } else if reloaded_at.elapsed() >= timeout {
+                            let reason = format!("Steam UI didn't come up within {}s after patching", timeout.as_secs());
+                            client.rollback(&reason).await;
+                            lifecycle::set(SteamState::CefUp);
+                        }
+                    }
+                    Ok(true) => {
+                        down_since = None;
+                        reported_no_debugger = false;
+                        match state {
+                            SteamState::SteamNotRunning | SteamState::CefUp => {
+                                lifecycle::set(SteamState::UiReady);
+                                client.prepare_ui(&mut reloaded_at).await;
+                            }
+                            SteamState::Reloading if reloaded_at.elapsed() >= UI_SETTLE_TIME => {
+                                restarts = 0;
+                                lifecycle::set(SteamState::Patched);
+                            }
+                            SteamState::Reloading => {}
+                            // A Steam update replaced the files under the running UI, patch
+                            // them again and reload it like when the UI comes up
+                            SteamState::UiReady | SteamState::Patched => {
+                                if updates.try_recv().is_ok() {
+                                    let patches = create_device().map(|device| device.get_patches());
+                                    if patches.is_some_and(|patches| Self::needs_patch(&patches)) {
+                                        println!("Steam UI was updated, repatching.");
+                                        client.prepare_ui(&mut reloaded_at).await;
                                     }
                                 }
                             }
                         }
-                        println!("Server not available, rechecking in 1 seconds...");
-                        tokio::time::sleep(Duration::from_millis(100)).await;
-                        continue;
                     }
                 }
-                // Notes for later: Maybe check for all tabs that would be in a normal session rather than one.
-
-                // Trial and sucess back to back runs
-                // 300 worked 1x
-                // 500 worked 2x bad 2x bad |NEW METHOD| 4x bad 1x
-                tokio::time::sleep(Duration::from_millis(500)).await;
+                tokio::select! {
+                    Some(event) = steam_processes.next() => match event {
+                        ProcessEvent::Started(process) => {
+                            steam_pids.insert(process.pid);
+                        }
+                        ProcessEvent::Exited(process) => {
+                            steam_pids.remove(&process.pid);
+                        }
+                    },
+                    _ = tokio::time::sleep(POLL_INTERVAL) => {}
+                }
             }
-        });  
-        Some(task)   
+        });
+        Some(task)
     }
 
-    
-
-    fn is_running() -> bool {
-        let mut sys = sysinfo::System::new_all();
-
-        // We need to update the system value to get the fresh process list
-        sys.refresh_all();
-
-        sys.processes()
-            .values()
-            .any(|process| process.name() == "steam")
+    // Patches the UI that just came up, reloading it when the files changed, and injects the
+    // injected patches. Moves to Reloading, Patched, or leaves UiReady when nothing applies.
+    async fn prepare_ui(&mut self, reloaded_at: &mut Instant) {
+        let patches = create_device().map(|device| device.get_patches());
+        let Some(patches) = patches.filter(|patches| !Self::is_quarantined(patches)) else {
+            lifecycle::set(SteamState::UiReady);
+            return;
+        };
+        let injected: Vec<Patch> = patches.iter().filter(|patch| patch.is_injected()).cloned().collect();
+        self.register_scripts(&injected).await;
+        let needs_patch = Self::needs_patch(&patches);
+        if needs_patch && self.repatch(patches) {
+            println!("Rebooting client");
+            *reloaded_at = Instant::now();
+            lifecycle::set(SteamState::Reloading);
+            println!(r#"{{"status": "patched"}}"#);
+            // The reloaded UI has to load its chunks before injecting
+            self.reload(UI_SETTLE_TIME).await;
+            self.inject(&injected).await;
+        } else {
+            self.inject(&injected).await;
+            lifecycle::set(if needs_patch { SteamState::UiReady } else { SteamState::Patched });
+        }
     }
 }
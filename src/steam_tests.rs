@@ -0,0 +1,165 @@
+use lazy_static::lazy_static;
+use serde_json::json;
+use std::future::Future;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use tokio::time::{sleep, Duration, Instant};
+
+use crate::cdp::{CdpClient, CdpError, TabSelector};
+use crate::config::{initialize_config, CONFIG};
+use crate::lifecycle::{self, SteamState};
+use crate::mock_cef::{MockCef, Reply};
+use crate::steam::SteamClient;
+
+// SteamClient against the fake debugger of mock_cef. The config and the Steam state are
+// global, so the tests take turns.
+
+lazy_static! {
+    static ref SERIAL: Mutex<()> = Mutex::new(());
+}
+
+// Points the config at `mock`
+fn use_mock(mock: &MockCef) {
+    let mut config = initialize_config();
+    config.cef_port = mock.port();
+    *CONFIG.lock().unwrap() = Some(config);
+}
+
+async fn eventually<F: Future<Output = bool>>(mut check: impl FnMut() -> F) -> bool {
+    let started = Instant::now();
+    while started.elapsed() < Duration::from_secs(10) {
+        if check().await {
+            return true;
+        }
+        sleep(Duration::from_millis(50)).await;
+    }
+    false
+}
+
+#[tokio::test]
+async fn replies_are_matched_to_their_requests() {
+    let _serial = SERIAL.lock().await;
+    let mock = MockCef::start(&["SharedJSContext"]);
+    use_mock(&mock);
+    mock.reply("Runtime.evaluate", Reply::Result(json!({ "result": { "type": "string", "value": "first" } })));
+    mock.reply("Runtime.evaluate", Reply::Result(json!({ "result": { "type": "string", "value": "second" } })));
+
+    let url = format!("ws://127.0.0.1:{}/devtools/page/0", mock.port());
+    let client = CdpClient::new(Arc::new(move || Box::pin(futures::future::ready(Some(url.clone())))), &[]);
+    let (a, b) = tokio::join!(client.query::<String>("'a'"), client.query::<String>("'b'"));
+
+    // The mock answers in the order the requests came in, whatever that was
+    let evaluated = mock.calls("Runtime.evaluate");
+    assert_eq!(evaluated.len(), 2);
+    let first = if evaluated[0].params["expression"] == "'a'" { (a, b) } else { (b, a) };
+    assert_eq!((first.0.unwrap(), first.1.unwrap()), ("first".to_string(), "second".to_string()));
+    assert!(evaluated.iter().all(|call| call.params["returnByValue"] == true && call.params["awaitPromise"] == true));
+}
+
+#[tokio::test]
+async fn errors_are_typed() {
+    let _serial = SERIAL.lock().await;
+    let mock = MockCef::start(&["SharedJSContext"]);
+    use_mock(&mock);
+    let mut steam = SteamClient::new();
+
+    mock.reply("Page.reload", Reply::Error(-32000, "Not allowed".to_string()));
+    match steam.call("Page.reload", json!({})).await {
+        Err(CdpError::Protocol { code, message }) => assert_eq!((code, message.as_str()), (-32000, "Not allowed")),
+        other => panic!("Expected a protocol error, got {:?}", other),
+    }
+
+    mock.reply("Runtime.evaluate", Reply::Exception("Uncaught ReferenceError".to_string()));
+    match steam.query::<bool>("missing()").await {
+        Err(CdpError::Exception(details)) => assert_eq!(details.text, "Uncaught ReferenceError"),
+        other => panic!("Expected an exception, got {:?}", other),
+    }
+
+    mock.reply("Runtime.evaluate", Reply::Result(json!({ "result": { "type": "string", "value": "yes" } })));
+    assert!(matches!(steam.query::<bool>("'yes'").await, Err(CdpError::Decode(_))));
+
+    // `undefined` has no value
+    mock.reply("Runtime.evaluate", Reply::Result(json!({ "result": { "type": "undefined" } })));
+    assert_eq!(steam.query::<Option<u32>>("undefined").await.unwrap(), None);
+
+    // Only the client watching Steam listens to the console
+    assert!(mock.calls("Runtime.enable").is_empty());
+}
+
+#[tokio::test]
+async fn reconnects_after_the_connection_drops() {
+    let _serial = SERIAL.lock().await;
+    let mock = MockCef::start(&["SharedJSContext"]);
+    use_mock(&mock);
+    let mut steam = SteamClient::with_console();
+
+    mock.reply("Page.reload", Reply::Disconnect);
+    assert!(matches!(steam.call("Page.reload", json!({})).await, Err(CdpError::Disconnected)));
+    assert!(steam.call("Page.reload", json!({})).await.is_ok());
+    assert_eq!(mock.calls("Page.reload").len(), 2);
+    // Domains are enabled again on the new connection
+    assert_eq!(mock.calls("Runtime.enable").len(), 2);
+}
+
+#[tokio::test]
+async fn reload_waits_for_the_load_event() {
+    let _serial = SERIAL.lock().await;
+    let mock = MockCef::start(&["SharedJSContext"]);
+    use_mock(&mock);
+    let mut steam = SteamClient::new();
+
+    let started = Instant::now();
+    steam.reload(Duration::from_secs(5)).await;
+    assert!(started.elapsed() < Duration::from_secs(5), "The load event never came");
+    assert_eq!(mock.calls("Page.enable").len(), 1);
+    assert_eq!(mock.calls("Page.reload").len(), 1);
+}
+
+#[tokio::test]
+async fn execute_only_runs_while_the_ui_is_up() {
+    let _serial = SERIAL.lock().await;
+    let mock = MockCef::start(&["SharedJSContext", "QuickAccess_uid2"]);
+    use_mock(&mock);
+    let mut steam = SteamClient::new();
+
+    lifecycle::set(SteamState::SteamNotRunning);
+    steam.execute("GamepadNavTree.m_Controller.OnButtonActionInternal(true, 28, 2)").await;
+    assert!(mock.calls("Runtime.evaluate").is_empty());
+
+    lifecycle::set(SteamState::UiReady);
+    steam.execute("SteamClient.URL.ExecuteSteamURL('steam://open/keyboard')").await;
+    steam.execute_in(&TabSelector::title("QuickAccess.*"), "GamepadNavTree.m_Controller.OnButtonActionInternal(true, 28, 2)").await;
+    lifecycle::set(SteamState::SteamNotRunning);
+    let evaluated = mock.calls("Runtime.evaluate");
+    assert_eq!(evaluated.len(), 2);
+    assert_eq!((evaluated[0].tab.as_str(), evaluated[1].tab.as_str()), ("SharedJSContext", "QuickAccess_uid2"));
+    let expression = evaluated[1].params["expression"].as_str().unwrap_or_default();
+    assert!(expression.starts_with("GamepadNavTree.m_Controller.OnButtonActionInternal(true, 28, 2)"));
+    assert!(expression.ends_with("//# sourceURL=steam-patch/execute"));
+}
+
+#[tokio::test]
+async fn watch_follows_steam_up_and_down() {
+    let _serial = SERIAL.lock().await;
+    let mock = MockCef::start(&[]);
+    use_mock(&mock);
+    lifecycle::set(SteamState::SteamNotRunning);
+    let mut transitions = lifecycle::subscribe();
+
+    let watch = SteamClient::watch().await.expect("watch always starts");
+    assert!(eventually(|| async { lifecycle::current() == SteamState::CefUp }).await);
+
+    mock.set_tabs(&["SharedJSContext"]);
+    // Without Steam files to patch the UI stays unpatched
+    assert!(eventually(|| async { lifecycle::current() == SteamState::UiReady }).await);
+
+    mock.set_up(false);
+    assert!(eventually(|| async { lifecycle::current() == SteamState::SteamNotRunning }).await);
+    watch.abort();
+
+    let mut seen = Vec::new();
+    while let Ok(transition) = transitions.try_recv() {
+        seen.push(transition.to);
+    }
+    assert_eq!(seen, [SteamState::CefUp, SteamState::UiReady, SteamState::SteamNotRunning]);
+}
@@ -0,0 +1,101 @@
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+
+use crate::utils::get_steam_dir;
+
+// Which Steam client is installed, read from the package directory of the Steam install:
+// `package/beta` names the beta branch and `package/steam_client_*.manifest` holds the build.
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct SteamVersion {
+    /// "stable" or the beta branch, ie. "publicbeta", without the "steamdeck_" prefix
+    pub branch: String,
+    /// Build number from the client manifest, `None` when it couldn't be read
+    pub build: Option<u64>,
+}
+
+/// Steam client builds a patch set is written for, fields left out match any build. Only
+/// sets with a build range make a build a supported one.
+#[derive(Deserialize, Debug, Default, Clone)]
+pub struct SteamRequirement {
+    #[serde(default)]
+    pub branches: Vec<String>,
+    pub min_build: Option<u64>,
+    pub max_build: Option<u64>,
+}
+
+impl fmt::Display for SteamVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.build {
+            Some(build) => write!(f, "Steam client build {} ({})", build, self.branch),
+            None => write!(f, "Steam client of unknown build ({})", self.branch),
+        }
+    }
+}
+
+impl SteamRequirement {
+    pub fn has_build_range(&self) -> bool {
+        self.min_build.is_some() || self.max_build.is_some()
+    }
+
+    pub fn matches(&self, version: &SteamVersion) -> bool {
+        if !self.branches.is_empty() && !self.branches.contains(&version.branch) {
+            return false;
+        }
+        if !self.has_build_range() {
+            return true;
+        }
+        // A build range can't be checked against an unknown build
+        match version.build {
+            Some(build) => {
+                self.min_build.is_none_or(|min| build >= min) && self.max_build.is_none_or(|max| build <= max)
+            }
+            None => false,
+        }
+    }
+}
+
+fn get_package_dir() -> PathBuf {
+    get_steam_dir().join("package")
+}
+
+fn read_branch() -> String {
+    let beta = fs::read_to_string(get_package_dir().join("beta")).unwrap_or_default();
+    let beta = beta.trim();
+    let branch = beta.strip_prefix("steamdeck_").unwrap_or(beta);
+    if branch.is_empty() {
+        "stable".to_string()
+    } else {
+        branch.to_string()
+    }
+}
+
+// Build of the most recently updated client manifest
+fn read_build() -> Option<u64> {
+    let mut manifests: Vec<_> = fs::read_dir(get_package_dir())
+        .ok()?
+        .flatten()
+        .filter(|entry| {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            name.starts_with("steam_client_") && name.ends_with(".manifest")
+        })
+        .filter_map(|entry| Some((entry.metadata().ok()?.modified().ok()?, entry.path())))
+        .collect();
+    manifests.sort();
+    let (_, path) = manifests.pop()?;
+
+    let content = fs::read_to_string(path).ok()?;
+    let version = Regex::new(r#""version"\s+"(\d+)""#).expect("Version regex is valid");
+    version.captures(&content)?.get(1)?.as_str().parse().ok()
+}
+
+/// Detects the installed Steam client, falls back to an unknown stable build.
+pub fn detect() -> SteamVersion {
+    SteamVersion {
+        branch: read_branch(),
+        build: read_build(),
+    }
+}
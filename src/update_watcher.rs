@@ -0,0 +1,80 @@
+use futures::StreamExt;
+use inotify::{EventMask, Inotify, WatchMask};
+use std::ffi::OsStr;
+use tokio::sync::mpsc;
+use tokio::time::{sleep, timeout, Duration};
+
+use crate::utils::get_steamui_dir;
+
+// Watches the steamui directory for chunk and library files a Steam update replaces,
+// so they get patched again without waiting for Steam to restart.
+
+// Time without any change before an update is considered done
+const SETTLE_TIME: Duration = Duration::from_secs(3);
+const RETRY_TIME: Duration = Duration::from_secs(5);
+
+fn is_ui_file(name: &OsStr) -> bool {
+    let name = name.to_string_lossy();
+    name.starts_with("chunk") || name.starts_with("library")
+}
+
+/// Starts watching steamui, a message is sent every time its files settle after a change.
+pub fn spawn() -> mpsc::Receiver<()> {
+    let (sender, receiver) = mpsc::channel(1);
+    tokio::spawn(async move {
+        let mut rewatch = false;
+        while !sender.is_closed() {
+            if let Err(e) = watch(&sender, rewatch).await {
+                eprintln!("Steam UI watcher: {}", e);
+            }
+            rewatch = true;
+            sleep(RETRY_TIME).await;
+        }
+    });
+    receiver
+}
+
+// Files may have been replaced while the directory wasn't watched, `changed` reports
+// them right away
+async fn watch(sender: &mpsc::Sender<()>, changed: bool) -> Result<(), String> {
+    let dir = get_steamui_dir();
+    let inotify = Inotify::init().map_err(|e| format!("Failed to initialize inotify: {}", e))?;
+    inotify
+        .watches()
+        .add(
+            &dir,
+            WatchMask::CREATE | WatchMask::MOVED_TO | WatchMask::CLOSE_WRITE | WatchMask::DELETE_SELF | WatchMask::MOVE_SELF,
+        )
+        .map_err(|e| format!("Failed to watch {:?}: {}", dir, e))?;
+    let mut events = inotify
+        .into_event_stream([0; 4096])
+        .map_err(|e| format!("Failed to read inotify events: {}", e))?;
+    println!("Watching {:?} for Steam updates", dir);
+
+    if changed {
+        let _ = sender.try_send(());
+    }
+
+    // Returns whether a UI file changed, fails when the directory itself went away
+    let next_change = |event: Option<std::io::Result<inotify::Event<std::ffi::OsString>>>| match event {
+        Some(Ok(event)) => {
+            if event.mask.intersects(EventMask::DELETE_SELF | EventMask::MOVE_SELF | EventMask::IGNORED) {
+                return Err(format!("{:?} was moved or removed", dir));
+            }
+            Ok(event.name.as_deref().map(is_ui_file).unwrap_or(false))
+        }
+        Some(Err(e)) => Err(format!("Failed to read inotify events: {}", e)),
+        None => Err("inotify event stream ended".to_string()),
+    };
+
+    loop {
+        while !next_change(events.next().await)? {}
+
+        // Steam replaces files one by one, wait for the update to settle
+        while let Ok(event) = timeout(SETTLE_TIME, events.next()).await {
+            next_change(event)?;
+        }
+        println!("Steam UI files changed");
+        let _ = sender.try_send(());
+    }
+}
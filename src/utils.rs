@@ -1,7 +1,9 @@
 use std::env;
-use std::io;
+use std::fs::{self, File, FileTimes, OpenOptions};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::io::{self, Write};
 use std::process::{Command, Output};
-use sysinfo::{ProcessExt, SystemExt};
 
 #[allow(dead_code)]
 pub fn run_command(command: &[&str]) -> io::Result<Output> {
@@ -16,30 +18,68 @@ pub fn run_command(command: &[&str]) -> io::Result<Output> {
 
 #[allow(dead_code)]
 pub fn get_username() -> String {
-    let args: Vec<String> = env::args().collect();
-
-    if args.len() != 2 {
-        return String::from("gamer");
+    // Tests never touch the Steam files of a real user
+    if cfg!(test) {
+        return String::from("steam-patch-test");
     }
+    env::args()
+        .find_map(|arg| arg.strip_prefix("--user=").map(String::from))
+        .unwrap_or_else(|| String::from("gamer"))
+}
+
+// True when `flag` was passed on the command line, ie. `check` or `--json`
+pub fn has_arg(flag: &str) -> bool {
+    env::args().skip(1).any(|arg| arg == flag)
+}
 
-    let arg = &args[1];
+// Working directory of steam-patch inside the user's home, ie. /home/gamer/steam-patch
+pub fn get_steam_patch_dir() -> PathBuf {
+    PathBuf::from(format!("/home/{}/steam-patch", get_username()))
+}
+
+pub fn get_steam_dir() -> PathBuf {
+    PathBuf::from(format!("/home/{}/.local/share/Steam", get_username()))
+}
 
-    if arg.starts_with("--user=") {
-        let username = arg.trim_start_matches("--user=");
-        String::from(username)
-    } else {
-        String::from("gamer")
+pub fn get_steamui_dir() -> PathBuf {
+    get_steam_dir().join("steamui")
+}
+
+// Steam opens its CEF debugger when started with this file in place
+pub fn get_remote_debugging_flag() -> PathBuf {
+    get_steam_dir().join(".cef-enable-remote-debugging")
+}
+
+/// Replaces `path` with `content` so a crash never leaves a truncated file: the content goes
+/// to a temp file in the same directory that is synced and renamed over the original.
+/// The owner, mode and mtime of the original are kept, we run as root inside the user's home.
+pub fn write_file_atomic(path: &Path, content: &[u8]) -> io::Result<()> {
+    let metadata = fs::metadata(path)?;
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let temp_path = dir.join(format!(".{}.steam-patch-{}", name, std::process::id()));
+
+    // Left over by a crash of an earlier run with the same pid
+    let _ = fs::remove_file(&temp_path);
+    let written = (|| {
+        let mut file = OpenOptions::new().write(true).create_new(true).open(&temp_path)?;
+        file.write_all(content)?;
+        file.set_permissions(metadata.permissions())?;
+        std::os::unix::fs::fchown(&file, Some(metadata.uid()), Some(metadata.gid()))?;
+        file.set_times(FileTimes::new().set_accessed(metadata.accessed()?).set_modified(metadata.modified()?))?;
+        file.sync_all()?;
+        fs::rename(&temp_path, path)
+    })();
+    if written.is_err() {
+        let _ = fs::remove_file(&temp_path);
     }
+    written?;
+
+    // Persist the rename itself
+    File::open(dir)?.sync_all()
 }
 
 #[allow(dead_code)]
 fn is_steam_running() -> bool {
-    let mut sys = sysinfo::System::new_all();
-
-    // We need to update the system value to get the fresh process list
-    sys.refresh_all();
-
-    sys.processes()
-        .values()
-        .any(|process| process.name() == "steam")
+    crate::process_watcher::find("steam").is_some()
 }
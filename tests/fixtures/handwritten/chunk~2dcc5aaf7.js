@@ -0,0 +1 @@
+"use strict";(self.webpackChunksteamui=self.webpackChunksteamui||[]).push([[3421],{61337:(e,t,n)=>{n.d(t,{Z:()=>u});var a=n(7552),l=n(56437),i=n(89526),s=n(84745),p=n(31492);function r(e){const t=(0,s.Uu)(),n=(0,s.XA)(),o=t?.nTDPLimitMin;return[o,t,n,e=>a((()=>p.Get().SetTDPLimit(e)))]}function c(e){const[t,n]=(0,i.useState)(e.value);return[t,200,1600,e=>n((()=>p.Get().SetGPUPerformanceManualMhz(e)))]}function g(e){const t=(0,s.Uu)();return t?[t.nTDPLimitMin,t.nTDPLimitMax,t.nTDPLimit,e=>p.Get().SetTDPLimit(e)]:null}function d(e){let t=(0,s.Uu)(),n=(0,s.XA)();return[t,n,e.value,e=>i((()=>p.Get().SetFPSLimit(e)))]}function u(e){const{button:t}=e;let n;switch(t){case 1:n="/steaminputglyphs/shared_color_button_a.svg";break;case 2:n="/steaminputglyphs/shared_color_button_b.svg";break;case 3:n="/steaminputglyphs/shared_color_button_x.svg";break;case 4:n="/steaminputglyphs/shared_color_button_y.svg";break;case 5:n="/steaminputglyphs/ps_color_button_x.svg";break;case 6:n="/steaminputglyphs/ps_color_button_circle.svg";break;case 7:n="/steaminputglyphs/ps_color_button_square.svg";break;case 8:n="/steaminputglyphs/ps_color_button_triangle.svg";break;case 9:n="/steaminputglyphs/ps4_button_options.svg";break;case 10:n="/steaminputglyphs/shared_button_menu.svg"}return i.createElement("img",{src:n,className:l.Glyph})}},44982:(e,t,n)=>{n.d(t,{f:()=>h});var a=n(89526),l=n(62211),A=n(20417);function h(e){const{type:t,button:n}=e;let o;if(t===l.sq.XBox){e="/steaminputglyphs/xbox_button_logo.svg"}else if(t===l.sq.PS4){e="/steaminputglyphs/ps4_button_logo.svg"}else{e="/steaminputglyphs/sd_button_menu.svg"}return a.createElement("img",{src:e,className:A.Logo})}},18203:(e,t,n)=>{n.d(t,{B:()=>o});var a=n(31492),c=n(69631);function o(e){SteamClient.System.Perf.RegisterForStateChanges((e=>{const t=c.Hm.deserializeBinary(e).toObject();Object.keys(t).forEach((e=>a.Get().UpdatePerfState(e,t[e])))}))}},71604:(e,t,n)=>{n.d(t,{q:()=>m});var x=n(73301);function Ue(e,t){return e.GetAppOverviewByAppID(t)}function m(e,t){const n=(0,x.we)("#DownloadComplete_Title"),r=Ue(n,t.data.appid());const s=(0,x.Q2)();return s.ShowToast({title:n,body:r.display_name})}}}]);
@@ -0,0 +1 @@
+"use strict";(self.webpackChunksteamui=self.webpackChunksteamui||[]).push([[7203],{47011:(e,t,n)=>{n.d(t,{rp:()=>o});var a=n(85556),i=n(62211);class o{constructor(){this.m_bShowingSystemMenu=!1,this.m_rgControllers=new Map,"undefined"!=typeof SteamClient&&(this.m_hUnregisterControllerDigitalInput=SteamClient.Input.RegisterForControllerInputMessages(this.HandleSystemKeyEvents))}HandleSystemKeyEvents(e){for(const t of e)t.bState&&i.e.Get().OnControllerButtonPressed(t.nController,t.eAction)}}(0,a.gn)([i.ak],o.prototype,"HandleSystemKeyEvents",null)}}]);